@@ -0,0 +1,39 @@
+//! Compares rendering notifications with the cached TemplateEngine against
+//! parsing every template on every render, as rqpush did before.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use handlebars::Handlebars;
+use rqpush::Notification;
+use serde_json::json;
+
+const TITLE_TEMPLATE: &str = "[{{app}}] {{notification}}";
+const TEXT_TEMPLATE: &str = "{{notification}}\n\n--\nEmail generated by {{tagline}}";
+const HTML_TEMPLATE: &str = r#"<HTML lang="{{lang}}"><BODY>{{notification}}<P><SMALL>Email generated by {{tagline}}.</SMALL></P></BODY></HTML>"#;
+
+fn notification() -> Notification {
+    let mut notification = Notification::init("example", "An example", "This is an example notification.");
+    notification.set_tagline("An example app");
+    notification
+}
+
+fn render(c: &mut Criterion) {
+    let notification = notification();
+    c.bench_function("render cached", |b| b.iter(|| black_box(notification.render().unwrap())));
+
+    let values = json!({
+        "app": "example",
+        "tagline": "An example app",
+        "lang": "en",
+        "notification": "This is an example notification.",
+    });
+    c.bench_function("render uncached", |b| {
+        b.iter(|| {
+            for template in &[TITLE_TEMPLATE, TEXT_TEMPLATE, HTML_TEMPLATE, TEXT_TEMPLATE, HTML_TEMPLATE] {
+                black_box(Handlebars::new().render_template(template, &values).unwrap());
+            }
+        })
+    });
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+use rqpush::Notification;
+
+#[tokio::main]
+async fn main() {
+    // Create a notification by setting the app name, a notification title, and a short message.
+    let notification = Notification::init("Example", "An example", "This is an example notification.");
+
+    // Send the notification without blocking the runtime: in this example we send it to port 8000
+    // on localhost, with a priority of 55. We do not set a TTL nor a shared key.
+    match notification.send_async("http://localhost:8000", 55, 0, None).await {
+        Ok(r) => println!("Success: {:?}", r),
+        Err(e) => println!("Failure: {:?}", e),
+    }
+}
@@ -0,0 +1,20 @@
+use rqpush::{Notification, RqueueClient};
+
+fn main() {
+    // Build a client once: every notification sent through it reuses the same connection pool.
+    // Notifications default to a priority of 55 and a TTL of 60 seconds, salted with "foo".
+    let client = RqueueClient::builder("http://localhost:8000")
+        .shared_secret("foo")
+        .priority(55)
+        .ttl(60)
+        .build_blocking()
+        .expect("failed to build client");
+
+    for device in &["iPhone", "laptop"] {
+        let notification = Notification::init("Example", "New device", &format!("A new device joined: {}", device));
+        match client.send(&notification) {
+            Ok(r) => println!("Success: {:?}", r),
+            Err(e) => println!("Failure: {:?}", e),
+        }
+    }
+}
@@ -2,7 +2,7 @@ use rqpush::Notification;
 
 fn main() {
     // Create a notification by setting the app name, a notification title, and a short message.
-    let mut notification = Notification::init("Example", "An example", "This is an example notification.");
+    let notification = Notification::init("Example", "An example", "This is an example notification.");
 
     // Send the notification: in this example we send it to port 8000 on localhost, with a priority
     // of 55. We do not set a TTL nor a shared key.
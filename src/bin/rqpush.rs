@@ -0,0 +1,263 @@
+//! Sends a notification to RQueue from the command line, for shell scripts and
+//! cron jobs. Built with the `cli` feature.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use clap::Parser;
+use rqpush::blocking::RqueueClient;
+use rqpush::{Error, Notification, RejectionKind, Signer};
+use serde_json::Value;
+
+/// The notification was accepted.
+const EXIT_OK: i32 = 0;
+/// A notification that can't be built, or was rejected as invalid (EX_DATAERR).
+const EXIT_DATAERR: i32 = 65;
+/// The body or a template file couldn't be read (EX_NOINPUT).
+const EXIT_NOINPUT: i32 = 66;
+/// RQueue couldn't be reached (EX_UNAVAILABLE).
+const EXIT_UNAVAILABLE: i32 = 69;
+/// RQueue failed or is overloaded, try again later (EX_TEMPFAIL).
+const EXIT_TEMPFAIL: i32 = 75;
+/// RQueue rejected the sha256 or signature (EX_NOPERM).
+const EXIT_NOPERM: i32 = 77;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rqpush",
+    version,
+    about = "Send a notification to RQueue.",
+    after_help = "The body is read from stdin unless --short-text is set.\n\n\
+                  Exit codes: 0 sent, 2 invalid arguments, 65 invalid notification or rejected by RQueue as invalid, \
+                  66 unreadable input, 69 RQueue unreachable, 75 RQueue failed or overloaded, \
+                  77 authentication rejected."
+)]
+struct Args {
+    /// RQueue server URL.
+    #[arg(short, long, env = "RQPUSH_SERVER", default_value = "http://127.0.0.1:8000")]
+    server: String,
+    /// Name of the application generating the notification.
+    #[arg(short, long)]
+    app: String,
+    /// Notification title, for example used as an email subject.
+    #[arg(short, long)]
+    title: String,
+    /// Notification body, read from stdin if not set.
+    #[arg(long)]
+    short_text: Option<String>,
+    /// Category used for filtering and routing.
+    #[arg(short, long)]
+    category: Option<String>,
+    /// URL of the project generating the notification.
+    #[arg(long)]
+    url: Option<String>,
+    /// Description of the project generating the notification.
+    #[arg(long)]
+    tagline: Option<String>,
+    /// Two letter language code.
+    #[arg(long)]
+    lang: Option<String>,
+    /// Priority from 0-255, higher is more important.
+    #[arg(short, long, default_value_t = 0)]
+    priority: u8,
+    /// Seconds the notification is valid for, 0 never expires.
+    #[arg(long, default_value_t = 0)]
+    ttl: u32,
+    /// Shared secret salting the sha256.
+    #[arg(long, env = "RQPUSH_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+    /// Sign with HMAC-SHA256 using the shared secret, instead of salting the sha256.
+    #[arg(long, requires = "secret")]
+    hmac: bool,
+    /// Seconds to wait for RQueue before giving up.
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Template value as key=value, may be repeated.
+    #[arg(long = "value", value_name = "KEY=VALUE", value_parser = parse_pair)]
+    values: Vec<(String, String)>,
+    /// Template value as key=<json>, for structured data such as lists.
+    #[arg(long = "json-value", value_name = "KEY=JSON", value_parser = parse_json_pair)]
+    json_values: Vec<(String, Value)>,
+    /// File containing the title template.
+    #[arg(long, value_name = "FILE")]
+    title_template: Option<PathBuf>,
+    /// File containing the short text template.
+    #[arg(long, value_name = "FILE")]
+    text_template: Option<PathBuf>,
+    /// File containing the short html template.
+    #[arg(long, value_name = "FILE")]
+    html_template: Option<PathBuf>,
+    /// File containing the long text template.
+    #[arg(long, value_name = "FILE")]
+    long_text_template: Option<PathBuf>,
+    /// File containing the long html template.
+    #[arg(long, value_name = "FILE")]
+    long_html_template: Option<PathBuf>,
+}
+
+fn parse_pair(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {}", pair)),
+    }
+}
+
+fn parse_json_pair(pair: &str) -> Result<(String, Value), String> {
+    let (key, json) = parse_pair(pair)?;
+    let value = serde_json::from_str(&json).map_err(|e| format!("invalid JSON for {}: {}", key, e))?;
+    Ok((key, value))
+}
+
+fn read_template(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|e| Error::Io {
+        context: format!("reading template {}", path.display()),
+        source: e,
+    })
+}
+
+fn build(args: &Args) -> Result<Notification, Error> {
+    let short_text = match &args.short_text {
+        Some(short_text) => short_text.clone(),
+        None => {
+            let mut body = String::new();
+            io::stdin().read_to_string(&mut body).map_err(|e| Error::Io {
+                context: "reading body from stdin".to_string(),
+                source: e,
+            })?;
+            body.trim_end().to_string()
+        }
+    };
+    let mut notification = Notification::try_init(&args.app, &args.title, &short_text)?;
+    if let Some(category) = &args.category {
+        notification.set_category(category);
+    }
+    if let Some(url) = &args.url {
+        notification.set_url(url);
+    }
+    if let Some(tagline) = &args.tagline {
+        notification.set_tagline(tagline);
+    }
+    if let Some(lang) = &args.lang {
+        notification.set_lang(lang);
+    }
+    for (key, value) in &args.values {
+        notification.add_value(key.clone(), value.clone());
+    }
+    for (key, value) in &args.json_values {
+        notification.add_serde_json_value(key.clone(), value.clone());
+    }
+    if let Some(path) = &args.title_template {
+        notification.set_title_template(read_template(path)?);
+    }
+    if let Some(path) = &args.text_template {
+        notification.set_short_text_template(read_template(path)?);
+    }
+    if let Some(path) = &args.html_template {
+        notification.set_short_html_template(read_template(path)?);
+    }
+    if let Some(path) = &args.long_text_template {
+        notification.set_long_text_template(read_template(path)?);
+    }
+    if let Some(path) = &args.long_html_template {
+        notification.set_long_html_template(read_template(path)?);
+    }
+    Ok(notification)
+}
+
+fn send(args: &Args, notification: &Notification) -> Result<(), Error> {
+    let mut builder = RqueueClient::builder(&args.server).priority(args.priority).ttl(args.ttl);
+    builder = match &args.secret {
+        Some(secret) if args.hmac => builder.signer(Signer::HmacSha256(secret.clone())),
+        Some(secret) => builder.shared_secret(secret),
+        None => builder,
+    };
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    builder.build_blocking()?.send(notification)?;
+    Ok(())
+}
+
+/// Maps the outcome of sending to an exit code scripts can act on.
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::Io { .. } => EXIT_NOINPUT,
+        Error::Transport { .. } | Error::Resolve { .. } => EXIT_UNAVAILABLE,
+        Error::Rejected { kind: RejectionKind::Auth, .. } => EXIT_NOPERM,
+        Error::Rejected { kind: RejectionKind::Server, .. } | Error::Spooled { .. } => EXIT_TEMPFAIL,
+        _ => EXIT_DATAERR,
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let result = build(&args).and_then(|notification| send(&args, &notification));
+    match result {
+        Ok(()) => process::exit(EXIT_OK),
+        Err(e) => {
+            eprintln!("rqpush: {}", e);
+            process::exit(exit_code(&e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pair() {
+        assert_eq!(parse_pair("host=db1"), Ok(("host".to_string(), "db1".to_string())));
+        assert_eq!(parse_pair("query=a=b"), Ok(("query".to_string(), "a=b".to_string())));
+        assert_eq!(parse_pair("empty="), Ok(("empty".to_string(), "".to_string())));
+        assert!(parse_pair("=db1").is_err());
+        assert!(parse_pair("host").is_err());
+    }
+
+    #[test]
+    fn test_parse_json_pair() {
+        assert_eq!(parse_json_pair(r#"devices=["laptop", "phone"]"#), Ok(("devices".to_string(), serde_json::json!(["laptop", "phone"]))));
+        assert_eq!(parse_json_pair("count=3"), Ok(("count".to_string(), serde_json::json!(3))));
+        assert!(parse_json_pair("=3").is_err());
+        assert!(parse_json_pair("devices").is_err());
+        let error = parse_json_pair("devices=[laptop").unwrap_err();
+        assert!(error.starts_with("invalid JSON for devices"), "{}", error);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let rejected = |status, kind| Error::Rejected {
+            server: "http://127.0.0.1:8000".to_string(),
+            status,
+            kind,
+            body: String::new(),
+        };
+        assert_eq!(exit_code(&rejected(401, RejectionKind::Auth)), EXIT_NOPERM);
+        assert_eq!(exit_code(&rejected(503, RejectionKind::Server)), EXIT_TEMPFAIL);
+        assert_eq!(exit_code(&rejected(400, RejectionKind::Validation)), EXIT_DATAERR);
+        let spooled = Error::Spooled {
+            path: PathBuf::from("/var/spool/rqpush/entry.json"),
+            source: Box::new(rejected(503, RejectionKind::Server)),
+        };
+        assert_eq!(exit_code(&spooled), EXIT_TEMPFAIL);
+
+        let missing = read_template(Path::new("/nonexistent/title.hbs")).unwrap_err();
+        assert_eq!(exit_code(&missing), EXIT_NOINPUT);
+        let empty = Notification::try_init("example", "", "This is an example.").unwrap_err();
+        assert_eq!(exit_code(&empty), EXIT_DATAERR);
+        let resolve = Error::Resolve {
+            name: "_rqueue._tcp.example.com".to_string(),
+            reason: "no records".to_string(),
+        };
+        assert_eq!(exit_code(&resolve), EXIT_UNAVAILABLE);
+
+        // Nothing listens on port 1.
+        let notification = Notification::init("example", "An example", "This is an example.");
+        let unreachable = RqueueClient::builder("http://127.0.0.1:1").build_blocking().unwrap().send(&notification).unwrap_err();
+        assert!(matches!(unreachable, Error::Transport { .. }));
+        assert_eq!(exit_code(&unreachable), EXIT_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,122 @@
+//! A blocking RqueueClient, for applications that don't run an async runtime.
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, error};
+use tokio::runtime::Runtime;
+
+use crate::{Error, Fanout, FanoutReport, FlushReport, Message, Notification, RqueueClientBuilder, SendReceipt};
+
+#[derive(Debug, Clone)]
+/// The blocking equivalent of `rqpush::RqueueClient`, created with
+/// `RqueueClientBuilder::build_blocking`. It drives an async client on a private
+/// single-threaded runtime, so it must not be used from within an async runtime.
+pub struct RqueueClient {
+    inner: crate::RqueueClient,
+    runtime: Arc<Runtime>,
+}
+
+impl RqueueClient {
+    /// Creates a client sending to `server` with default settings.
+    pub fn new(server: &str) -> Result<RqueueClient, Error> {
+        RqueueClient::builder(server).build_blocking()
+    }
+
+    /// Returns a builder for configuring a client sending to `server`.
+    pub fn builder(server: &str) -> RqueueClientBuilder {
+        crate::RqueueClient::builder(server)
+    }
+
+    pub(crate) fn from_async(inner: crate::RqueueClient) -> Result<RqueueClient, Error> {
+        debug!("rqpush building blocking runtime for {}", inner.server());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Io {
+                context: "building blocking client runtime".to_string(),
+                source: e,
+            })?;
+        Ok(RqueueClient {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The RQueue server this client sends notifications to.
+    pub fn server(&self) -> &str {
+        self.inner.server()
+    }
+
+    /// Compiles and sends the notification, blocking until the server responds,
+    /// see `rqpush::RqueueClient::send`.
+    pub fn send(&self, notification: &Notification) -> Result<SendReceipt, Error> {
+        self.runtime.block_on(self.inner.send(notification))
+    }
+
+    /// Compiles and sends many notifications, returning a result for each, see
+    /// `rqpush::RqueueClient::send_batch`.
+    pub fn send_batch(&self, notifications: &[Notification]) -> Vec<Result<SendReceipt, Error>> {
+        self.runtime.block_on(self.inner.send_batch(notifications))
+    }
+
+    /// Compiles the notification once and delivers it to every server in the
+    /// fanout, see `rqpush::RqueueClient::send_fanout`.
+    pub fn send_fanout(&self, notification: &Notification, fanout: &Fanout) -> Result<FanoutReport, Error> {
+        self.runtime.block_on(self.inner.send_fanout(notification, fanout))
+    }
+
+    /// Posts an already compiled message to `server`.
+    pub(crate) fn deliver(&self, server: &str, message: &Message) -> Result<SendReceipt, Error> {
+        self.runtime.block_on(self.inner.deliver(server, message))
+    }
+
+    /// Attempts to redeliver every spooled message, see
+    /// `rqpush::RqueueClient::flush_spool`.
+    pub fn flush_spool(&self) -> Result<FlushReport, Error> {
+        self.runtime.block_on(self.inner.flush_spool())
+    }
+
+    /// Starts a thread that flushes the spool every `interval`, until the
+    /// returned SpoolFlusher is stopped or dropped.
+    pub fn spawn_spool_flusher(&self, interval: Duration) -> SpoolFlusher {
+        let client = self.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            if let Err(e) = client.flush_spool() {
+                error!("rqpush failed to flush spool: {}", e);
+            }
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => break,
+            }
+        });
+        SpoolFlusher {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A background thread flushing a spool, stopped when dropped.
+pub struct SpoolFlusher {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SpoolFlusher {
+    /// Stops the flusher, waiting for any flush in progress to finish.
+    pub fn stop(self) {}
+}
+
+impl Drop for SpoolFlusher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use log::{debug, error, info, trace, warn};
+use serde_json::Value;
+
+use crate::receipt::interpret_response;
+use crate::spool::unix_now;
+use crate::{ContentKey, Error, Fanout, FanoutReport, FlushReport, Message, Notification, RejectionKind, RetryPolicy, Router, SendReceipt, Signer, Spool, SrvResolver};
+
+/// Default priority applied to notifications that don't set their own.
+pub const DEFAULT_PRIORITY: u8 = 0;
+/// Default TTL applied to notifications that don't set their own, 0 never expires.
+pub const DEFAULT_TTL: u32 = 0;
+/// Default number of notifications send_batch delivers at once.
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Clone)]
+/// A reusable client for sending notifications to a single RQueue server, or to
+/// the servers an SRV service name resolves to. The underlying HTTP connection
+/// pool is kept for the lifetime of the client, so keep-alive connections and
+/// TLS sessions are reused across notifications.
+pub struct RqueueClient {
+    http: reqwest::Client,
+    server: String,
+    signer: Signer,
+    content_key: Option<ContentKey>,
+    priority: u8,
+    ttl: u32,
+    retry_policy: RetryPolicy,
+    spool: Option<Spool>,
+    srv_resolver: Option<SrvResolver>,
+    router: Option<Arc<Router>>,
+    batch_concurrency: usize,
+    bulk_path: Option<String>,
+}
+
+impl RqueueClient {
+    /// Creates a client sending to `server` with default settings.
+    pub fn new(server: &str) -> Result<RqueueClient, Error> {
+        RqueueClient::builder(server).build()
+    }
+
+    /// Returns a builder for configuring a client sending to `server`.
+    pub fn builder(server: &str) -> RqueueClientBuilder {
+        RqueueClientBuilder::new(server)
+    }
+
+    /// The RQueue server this client sends notifications to, or the SRV service
+    /// name if an SrvResolver is configured.
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Compiles and sends the notification. If a Router is configured, the first
+    /// matching route picks the server. The notification's own priority and TTL
+    /// are used when set, then the route's, otherwise the client defaults apply.
+    pub async fn send(&self, notification: &Notification) -> Result<SendReceipt, Error> {
+        let (server, message) = self.prepare(notification)?;
+        self.deliver(&server, &message).await
+    }
+
+    /// Compiles and sends many notifications, delivering up to the configured
+    /// batch concurrency at a time over the client's connection pool. Returns a
+    /// result for each notification, in the same order.
+    ///
+    /// If a bulk path is configured, the messages for each server are first
+    /// posted together as a JSON array in a single request. They're only sent
+    /// individually if the bulk request provably wasn't processed, because the
+    /// server couldn't be reached or doesn't support bulk requests (404, 405 or
+    /// 501). Other failures are returned for each message as Error::Bulk, as
+    /// resending could deliver them twice.
+    pub async fn send_batch(&self, notifications: &[Notification]) -> Vec<Result<SendReceipt, Error>> {
+        let mut results: Vec<Option<Result<SendReceipt, Error>>> = notifications.iter().map(|_| None).collect();
+        let mut pending = Vec::new();
+        for (index, notification) in notifications.iter().enumerate() {
+            match self.prepare(notification) {
+                Ok((server, message)) => pending.push((index, server, message)),
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+
+        if let Some(bulk_path) = &self.bulk_path {
+            let mut by_server: Vec<(String, Vec<(usize, Message)>)> = Vec::new();
+            for (index, server, message) in pending.drain(..) {
+                match by_server.iter_mut().find(|(s, _)| *s == server) {
+                    Some((_, messages)) => messages.push((index, message)),
+                    None => by_server.push((server, vec![(index, message)])),
+                }
+            }
+            for (server, messages) in by_server {
+                let batch: Vec<&Message> = messages.iter().map(|(_, m)| m).collect();
+                match self.post_bulk(&server, bulk_path, &batch).await {
+                    Ok(bulk_results) => {
+                        for ((index, _), result) in messages.iter().zip(bulk_results) {
+                            results[*index] = Some(result);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("rqpush bulk send to {} not processed, sending individually: {}", &server, e);
+                        pending.extend(messages.into_iter().map(|(index, message)| (index, server.clone(), message)));
+                    }
+                }
+            }
+        }
+
+        let delivered: Vec<(usize, Result<SendReceipt, Error>)> = stream::iter(pending)
+            .map(|(index, server, message)| async move { (index, self.deliver(&server, &message).await) })
+            .buffer_unordered(self.batch_concurrency)
+            .collect()
+            .await;
+        for (index, result) in delivered {
+            results[index] = Some(result);
+        }
+        results.into_iter().map(|r| r.expect("every notification has a result")).collect()
+    }
+
+    /// Routes and compiles the notification, returning the server it should be
+    /// sent to and the message to send.
+    fn prepare(&self, notification: &Notification) -> Result<(String, Message), Error> {
+        let route = self.router.as_ref().and_then(|r| r.route(notification));
+        let server = route.map_or(self.server.as_str(), |r| r.server.as_str()).to_string();
+        let priority = notification.priority.or_else(|| route.and_then(|r| r.priority)).unwrap_or(self.priority);
+        let ttl = notification.ttl.or_else(|| route.and_then(|r| r.ttl)).unwrap_or(self.ttl);
+        let message = notification.compile_sealed(&server, priority, ttl, self.content_key.as_ref(), &self.signer)?;
+        Ok((server, message))
+    }
+
+    /// Compiles the notification once and delivers it to every server in the
+    /// fanout, each with its own retries and spooling. Returns the per-server
+    /// outcomes if they satisfy the fanout's policy, otherwise Error::Fanout.
+    pub async fn send_fanout(&self, notification: &Notification, fanout: &Fanout) -> Result<FanoutReport, Error> {
+        if fanout.servers.is_empty() {
+            return Err(Error::InvalidInput {
+                field: "fanout",
+                reason: "no servers".to_string(),
+            });
+        }
+        let priority = notification.priority.unwrap_or(self.priority);
+        let ttl = notification.ttl.unwrap_or(self.ttl);
+        let servers = fanout.servers.join(", ");
+        let message = notification.compile_sealed(&servers, priority, ttl, self.content_key.as_ref(), &self.signer)?;
+
+        let results = if fanout.parallel {
+            join_all(fanout.servers.iter().map(|server| self.deliver(server, &message))).await
+        } else {
+            let mut results = Vec::with_capacity(fanout.servers.len());
+            for server in &fanout.servers {
+                results.push(self.deliver(server, &message).await);
+            }
+            results
+        };
+        let report = FanoutReport {
+            policy: fanout.policy,
+            outcomes: fanout.servers.iter().cloned().zip(results).collect(),
+        };
+        debug!("rqpush fanout to {}: {} of {} succeeded", &servers, report.successes(), report.outcomes.len());
+        if report.is_success() {
+            Ok(report)
+        } else {
+            Err(Error::Fanout(Box::new(report)))
+        }
+    }
+
+    /// Posts an already compiled message to `server`, retrying according to the
+    /// client's RetryPolicy. If it still can't be delivered and a Spool is
+    /// configured, the message is spooled and Error::Spooled is returned.
+    pub(crate) async fn deliver(&self, server: &str, message: &Message) -> Result<SendReceipt, Error> {
+        let started = message.timestamp.unwrap_or_else(unix_now);
+        let error = match self.deliver_to(server, message).await {
+            Ok(receipt) => return Ok(receipt),
+            Err(e) => e,
+        };
+        let spool = match &self.spool {
+            Some(spool) if is_spoolable(&error) => spool,
+            _ => return Err(error),
+        };
+        match spool.push_at(server, message, started) {
+            Ok(path) => Err(Error::Spooled {
+                path,
+                source: Box::new(error),
+            }),
+            Err(e) => {
+                error!("rqpush failed to spool undelivered message: {}", e);
+                Err(error)
+            }
+        }
+    }
+
+    /// Posts an already compiled message to `server`, retrying according to the
+    /// client's RetryPolicy but never past the message's TTL.
+    async fn deliver_to(&self, server: &str, message: &Message) -> Result<SendReceipt, Error> {
+        let started = Instant::now();
+        let expires = match message.ttl {
+            Some(ttl) if ttl > 0 => Some(started + Duration::from_secs(u64::from(ttl))),
+            _ => None,
+        };
+        let mut attempt = 1;
+        loop {
+            let error = match self.post_any(server, message).await {
+                Ok(receipt) => return Ok(receipt),
+                Err(e) => e,
+            };
+            if !self.retry_policy.allows_attempt(attempt) || !self.retry_policy.is_retryable(&error) {
+                return Err(error);
+            }
+            let delay = self.retry_policy.delay(attempt);
+            if let Some(expires) = expires {
+                if Instant::now() + delay >= expires {
+                    debug!("rqpush not retrying, ttl of {:?} would expire first", message.ttl);
+                    return Err(error);
+                }
+            }
+            warn!("rqpush attempt {} failed ({}), retrying in {:?}", attempt, error, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Makes a single attempt to post the message to `server`. If `server` is an
+    /// SRV service name each target is tried in turn, failing over to the next
+    /// when a connection can't be established.
+    async fn post_any(&self, server: &str, message: &Message) -> Result<SendReceipt, Error> {
+        let mut last_error = None;
+        for url in self.urls(server).await? {
+            match self.post(&url, message).await {
+                Err(Error::Transport { server, source }) if source.is_connect() => {
+                    warn!("rqpush failed to connect to {}, failing over: {}", &server, source);
+                    last_error = Some(Error::Transport { server, source });
+                }
+                result => return result,
+            }
+        }
+        // urls never returns an empty list.
+        Err(last_error.expect("no urls"))
+    }
+
+    /// Makes a single attempt to post all messages to `server` joined with the
+    /// bulk path, failing over between SRV targets like `post_any`. Returns a
+    /// result per message once RQueue may have processed the request, or an
+    /// error if it provably didn't and the messages can be sent individually.
+    async fn post_bulk(&self, server: &str, bulk_path: &str, messages: &[&Message]) -> Result<Vec<Result<SendReceipt, Error>>, Error> {
+        let mut last_error = None;
+        for url in self.urls(server).await? {
+            let url = format!("{}/{}", url.trim_end_matches('/'), bulk_path.trim_start_matches('/'));
+            trace!("rqpush post_bulk: server({}) messages({})", &url, messages.len());
+            let response = match self.http.post(&url).json(messages).send().await {
+                Ok(response) => response,
+                Err(e) if e.is_connect() => {
+                    warn!("rqpush failed to connect to {}, failing over: {}", &url, e);
+                    last_error = Some(transport_error(&url, e));
+                    continue;
+                }
+                Err(e) => return Ok(bulk_failed(messages, transport_error(&url, e))),
+            };
+            let status = response.status().as_u16();
+            let body = match response.text().await {
+                Ok(body) => body,
+                Err(e) => return Ok(bulk_failed(messages, transport_error(&url, e))),
+            };
+            // The server doesn't support bulk requests.
+            if let 404 | 405 | 501 = status {
+                return Err(interpret_response(&url, messages[0], status, body).expect_err("not a 2xx status"));
+            }
+            // RQueue may answer with one result per message, or one for the batch.
+            let per_message = match serde_json::from_str(&body) {
+                Ok(Value::Array(bodies)) if bodies.len() == messages.len() => Some(bodies),
+                _ => None,
+            };
+            let results = messages
+                .iter()
+                .enumerate()
+                .map(|(index, message)| {
+                    let mut receipt = interpret_response(&url, message, status, body.clone())?;
+                    if let Some(bodies) = &per_message {
+                        receipt.body = Some(bodies[index].clone());
+                    }
+                    Ok(receipt)
+                })
+                .collect();
+            return Ok(results);
+        }
+        Err(last_error.expect("no urls"))
+    }
+
+    /// The URLs to try for `server`, in order. Only the client's own server is
+    /// resolved as an SRV service name.
+    async fn urls(&self, server: &str) -> Result<Vec<String>, Error> {
+        match &self.srv_resolver {
+            Some(resolver) if server == self.server => resolver.resolve_urls(server).await,
+            _ => Ok(vec![server.to_string()]),
+        }
+    }
+
+    /// Makes a single attempt to post the message. Responses with a non-2xx
+    /// status are returned as Error::Rejected.
+    async fn post(&self, server: &str, message: &Message) -> Result<SendReceipt, Error> {
+        trace!("rqpush post: server({}) message({:?})", &server, &message);
+        let response = self
+            .http
+            .post(server)
+            .json(message)
+            .send()
+            .await
+            .map_err(|e| transport_error(server, e))?;
+        let status = response.status().as_u16();
+        let body = response.text().await.map_err(|e| transport_error(server, e))?;
+        interpret_response(server, message, status, body)
+    }
+
+    /// Attempts to redeliver every spooled message to the server it was
+    /// originally sent to. Messages whose TTL has elapsed are dropped, and the
+    /// TTL of redelivered messages is reduced by the time spent in the spool.
+    /// Does nothing if no Spool is configured.
+    pub async fn flush_spool(&self) -> Result<FlushReport, Error> {
+        let mut report = FlushReport::default();
+        let spool = match &self.spool {
+            Some(spool) => spool,
+            None => return Ok(report),
+        };
+        for (path, entry) in spool.entries()? {
+            // Another flush, maybe in another process, may be redelivering it.
+            let claimed = match spool.claim(&path)? {
+                Some(claimed) => claimed,
+                None => {
+                    trace!("rqpush skipping claimed spool entry {}", path.display());
+                    continue;
+                }
+            };
+            let now = unix_now();
+            if entry.is_expired(now) {
+                debug!("rqpush dropping expired spool entry {}", path.display());
+                spool.remove(&claimed)?;
+                report.expired += 1;
+                continue;
+            }
+            let mut message = entry.message.clone();
+            if let Some(remaining) = entry.remaining_ttl(now) {
+                message.ttl = Some(remaining);
+            }
+            match self.post_any(&entry.server, &message).await {
+                Ok(_) => {
+                    spool.remove(&claimed)?;
+                    report.delivered += 1;
+                }
+                Err(e) if is_spoolable(&e) => {
+                    debug!("rqpush leaving {} in spool: {}", path.display(), e);
+                    spool.release(&claimed)?;
+                    report.remaining += 1;
+                }
+                Err(e) => {
+                    warn!("rqpush dropping spool entry {}: {}", path.display(), e);
+                    spool.remove(&claimed)?;
+                    report.rejected += 1;
+                }
+            }
+        }
+        info!("rqpush flushed spool: {:?}", report);
+        Ok(report)
+    }
+
+    /// Spawns a task on the current tokio runtime that flushes the spool every
+    /// `interval`. Abort the returned handle to stop it.
+    pub fn spawn_spool_flusher(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let client = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = client.flush_spool().await {
+                    error!("rqpush failed to flush spool: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// Returns true for failures that may succeed if the message is sent again later.
+fn is_spoolable(error: &Error) -> bool {
+    match error {
+        Error::Transport { .. } => true,
+        Error::Rejected { kind, .. } => *kind == RejectionKind::Server,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Builds an RqueueClient, or a blocking::RqueueClient when the `blocking`
+/// feature is enabled.
+pub struct RqueueClientBuilder {
+    server: String,
+    signer: Signer,
+    content_key: Option<ContentKey>,
+    priority: u8,
+    ttl: u32,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    retry_policy: RetryPolicy,
+    spool: Option<Spool>,
+    srv_resolver: Option<SrvResolver>,
+    router: Option<Router>,
+    batch_concurrency: usize,
+    bulk_path: Option<String>,
+}
+
+impl RqueueClientBuilder {
+    fn new(server: &str) -> RqueueClientBuilder {
+        RqueueClientBuilder {
+            server: server.to_string(),
+            signer: Signer::default(),
+            content_key: None,
+            priority: DEFAULT_PRIORITY,
+            ttl: DEFAULT_TTL,
+            timeout: None,
+            connect_timeout: None,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            retry_policy: RetryPolicy::never(),
+            spool: None,
+            srv_resolver: None,
+            router: None,
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            bulk_path: None,
+        }
+    }
+
+    /// Salt the sha256 of every notification with this shared secret, shorthand
+    /// for `signer(Signer::Legacy(Some(shared_secret)))`.
+    pub fn shared_secret(mut self, shared_secret: &str) -> RqueueClientBuilder {
+        self.signer = Signer::Legacy(Some(shared_secret.to_string()));
+        self
+    }
+
+    /// How every notification is authenticated (otherwise will default to an
+    /// unsalted Signer::Legacy).
+    pub fn signer(mut self, signer: Signer) -> RqueueClientBuilder {
+        self.signer = signer;
+        self
+    }
+
+    /// Encrypt the contents of every notification with this key, leaving
+    /// priority and TTL visible to RQueue (otherwise will default to plaintext).
+    pub fn content_key(mut self, content_key: ContentKey) -> RqueueClientBuilder {
+        self.content_key = Some(content_key);
+        self
+    }
+
+    /// Priority used for notifications that don't set their own.
+    pub fn priority(mut self, priority: u8) -> RqueueClientBuilder {
+        self.priority = priority;
+        self
+    }
+
+    /// TTL in seconds used for notifications that don't set their own.
+    pub fn ttl(mut self, ttl: u32) -> RqueueClientBuilder {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Total timeout for each request, from connecting until the response body
+    /// has been received.
+    pub fn timeout(mut self, timeout: Duration) -> RqueueClientBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for establishing a connection to the server.
+    pub fn connect_timeout(mut self, timeout: Duration) -> RqueueClientBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long an idle pooled connection is kept open.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> RqueueClientBuilder {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum number of idle connections kept open to the server.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> RqueueClientBuilder {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// How failed sends are retried (otherwise will default to RetryPolicy::never()).
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> RqueueClientBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Spool notifications that still can't be delivered after retrying, so they
+    /// can be redelivered later with `flush_spool`.
+    pub fn spool(mut self, spool: Spool) -> RqueueClientBuilder {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Treat the server as an SRV service name such as `_rqueue._tcp.example.com`,
+    /// resolving it to RQueue servers with `resolver`.
+    pub fn srv_resolver(mut self, resolver: SrvResolver) -> RqueueClientBuilder {
+        self.srv_resolver = Some(resolver);
+        self
+    }
+
+    /// Pick the server, priority and TTL of each notification with `router`. The
+    /// client's own server is used for notifications no route matches.
+    pub fn router(mut self, router: Router) -> RqueueClientBuilder {
+        self.router = Some(router);
+        self
+    }
+
+    /// Maximum number of notifications send_batch delivers at once (otherwise
+    /// will default to DEFAULT_BATCH_CONCURRENCY).
+    pub fn batch_concurrency(mut self, concurrency: usize) -> RqueueClientBuilder {
+        self.batch_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Path of an RQueue endpoint accepting a JSON array of messages, which
+    /// send_batch will use to deliver each server's messages in one request.
+    pub fn bulk_path(mut self, path: &str) -> RqueueClientBuilder {
+        self.bulk_path = Some(path.to_string());
+        self
+    }
+
+    /// Builds the async client.
+    pub fn build(self) -> Result<RqueueClient, Error> {
+        debug!("rqpush building client for {}", &self.server);
+        self.signer.validate()?;
+        let mut http = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            http = http.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            http = http.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            http = http.pool_max_idle_per_host(max);
+        }
+        Ok(RqueueClient {
+            http: http.build().map_err(|e| transport_error(&self.server, e))?,
+            server: self.server,
+            signer: self.signer,
+            content_key: self.content_key,
+            priority: self.priority,
+            ttl: self.ttl,
+            retry_policy: self.retry_policy,
+            spool: self.spool,
+            srv_resolver: self.srv_resolver,
+            router: self.router.map(Arc::new),
+            batch_concurrency: self.batch_concurrency,
+            bulk_path: self.bulk_path,
+        })
+    }
+
+    /// Builds the blocking client.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<crate::blocking::RqueueClient, Error> {
+        crate::blocking::RqueueClient::from_async(self.build()?)
+    }
+}
+
+/// Wraps a reqwest error with the server it occurred on.
+/// The same error for every message of a bulk request.
+fn bulk_failed(messages: &[&Message], error: Error) -> Vec<Result<SendReceipt, Error>> {
+    let error = Arc::new(error);
+    messages.iter().map(|_| Err(Error::Bulk(Arc::clone(&error)))).collect()
+}
+
+pub(crate) fn transport_error(server: &str, source: reqwest::Error) -> Error {
+    Error::Transport {
+        server: server.to_string(),
+        source,
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use log::{debug, trace};
+
+use crate::signing::signed_payload;
+use crate::{ContentKey, Error, Message, OutboundNotification, SignatureVersion, VerifyError};
+
+/// Loads an Ed25519 private key from a PKCS#8 PEM file ("BEGIN PRIVATE KEY"),
+/// or a file holding the raw 32 byte key, either as-is or hex encoded.
+pub fn load_signing_key<P: AsRef<Path>>(path: P) -> Result<SigningKey, Error> {
+    let path = path.as_ref();
+    let data = read_key(path)?;
+    if let Some(pem) = pem_text(&data) {
+        return SigningKey::from_pkcs8_pem(pem).map_err(|e| invalid_key(path, e.to_string()));
+    }
+    match raw_key(&data) {
+        Some(bytes) => Ok(SigningKey::from_bytes(&bytes)),
+        None => Err(invalid_key(path, "expected PEM or a 32 byte key".to_string())),
+    }
+}
+
+/// Loads an Ed25519 public key from a PEM file ("BEGIN PUBLIC KEY"), or a file
+/// holding the raw 32 byte key, either as-is or hex encoded.
+pub fn load_verifying_key<P: AsRef<Path>>(path: P) -> Result<VerifyingKey, Error> {
+    let path = path.as_ref();
+    let data = read_key(path)?;
+    if let Some(pem) = pem_text(&data) {
+        return VerifyingKey::from_public_key_pem(pem).map_err(|e| invalid_key(path, e.to_string()));
+    }
+    match raw_key(&data) {
+        Some(bytes) => VerifyingKey::from_bytes(&bytes).map_err(|e| invalid_key(path, e.to_string())),
+        None => Err(invalid_key(path, "expected PEM or a 32 byte key".to_string())),
+    }
+}
+
+fn read_key(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| Error::Io {
+        context: format!("reading key {}", path.display()),
+        source: e,
+    })
+}
+
+fn invalid_key(path: &Path, reason: String) -> Error {
+    Error::InvalidInput {
+        field: "key",
+        reason: format!("{}: {}", path.display(), reason),
+    }
+}
+
+/// The key file as text, if it's PEM encoded.
+fn pem_text(data: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(data).ok()?.trim();
+    if text.starts_with("-----BEGIN") {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+/// The key file as raw bytes, if it's exactly 32 bytes or 64 hex digits.
+fn raw_key(data: &[u8]) -> Option<[u8; 32]> {
+    if let Ok(bytes) = <[u8; 32]>::try_from(data) {
+        return Some(bytes);
+    }
+    let text = std::str::from_utf8(data).ok()?.trim();
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(text, &mut bytes).ok()?;
+    Some(bytes)
+}
+
+#[derive(Debug, Clone)]
+struct KeyringEntry {
+    app: String,
+    key: VerifyingKey,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Maps the key ids of Ed25519 signed messages to the public key and app they
+/// belong to, so receivers can verify a message was sent by the app it claims.
+pub struct Ed25519Keyring {
+    keys: HashMap<String, KeyringEntry>,
+    content_key: Option<ContentKey>,
+}
+
+impl Ed25519Keyring {
+    /// Creates an empty keyring.
+    pub fn new() -> Ed25519Keyring {
+        Ed25519Keyring::default()
+    }
+
+    /// Trusts `key` to sign notifications for `app`, replacing any key
+    /// previously added as `key_id`.
+    pub fn insert(&mut self, key_id: &str, app: &str, key: VerifyingKey) {
+        self.keys.insert(
+            key_id.to_string(),
+            KeyringEntry {
+                app: app.to_string(),
+                key,
+            },
+        );
+    }
+
+    /// Decrypts the contents of encrypted messages with `content_key` once their
+    /// signature is verified, so the app can be checked. Plaintext messages are
+    /// still accepted, encrypted ones are rejected as VerifyError::Encrypted
+    /// without it.
+    pub fn content_key(mut self, content_key: ContentKey) -> Ed25519Keyring {
+        self.content_key = Some(content_key);
+        self
+    }
+
+    /// As insert, loading the key with load_verifying_key.
+    pub fn insert_file<P: AsRef<Path>>(&mut self, key_id: &str, app: &str, path: P) -> Result<(), Error> {
+        let key = load_verifying_key(path)?;
+        self.insert(key_id, app, key);
+        Ok(())
+    }
+
+    /// Verifies a message signed by Signer::Ed25519, returning its notification
+    /// once the signature has been checked against the key named by
+    /// Message.key_id and the notification's app matches the key's app.
+    /// Timestamp and nonce are covered by the signature but not checked here.
+    pub fn verify(&self, message: &Message) -> Result<OutboundNotification, VerifyError> {
+        trace!("rqpush keyring verify: message({:?})", &message);
+        let signature = message.signature.as_deref().ok_or(VerifyError::MissingSignature)?;
+        if message.signature_version != Some(SignatureVersion::Ed25519V1) {
+            return Err(VerifyError::UnsupportedSignature(message.signature_version));
+        }
+        let key_id = message.key_id.as_deref().ok_or(VerifyError::MissingKeyId)?;
+        let entry = self.keys.get(key_id).ok_or_else(|| VerifyError::UnknownKey(key_id.to_string()))?;
+        let mut bytes = [0u8; 64];
+        hex::decode_to_slice(signature, &mut bytes).map_err(|_| VerifyError::SignatureMismatch)?;
+        entry
+            .key
+            .verify_strict(signed_payload(message).as_bytes(), &Signature::from_bytes(&bytes))
+            .map_err(|_| VerifyError::SignatureMismatch)?;
+        let notification: OutboundNotification = match (&self.content_key, message.encryption) {
+            (Some(content_key), Some(_)) => content_key.open(message)?,
+            (None, Some(_)) => return Err(VerifyError::Encrypted),
+            _ => serde_json::from_str(&message.contents).map_err(|e| VerifyError::MalformedContents(e.to_string()))?,
+        };
+        if notification.app != entry.app {
+            debug!("rqpush key {} may not sign for app {}", key_id, &notification.app);
+            return Err(VerifyError::AppMismatch {
+                key_id: key_id.to_string(),
+                expected: entry.app.clone(),
+                actual: notification.app,
+            });
+        }
+        Ok(notification)
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use log::trace;
+use sha2::Sha256;
+
+use crate::{Message, OutboundNotification, VerifyError};
+
+/// Length of the nonce prefixed to encrypted contents.
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Identifies how Message.contents was encrypted.
+pub enum Encryption {
+    /// XChaCha20-Poly1305 with a random 192-bit nonce. The contents are the
+    /// base64 encoded nonce followed by the ciphertext.
+    #[serde(rename = "xchacha20poly1305-v1")]
+    XChaCha20Poly1305V1,
+}
+
+impl Encryption {
+    fn label(self) -> &'static str {
+        match self {
+            Encryption::XChaCha20Poly1305V1 => "xchacha20poly1305-v1",
+        }
+    }
+}
+
+#[derive(Clone)]
+/// A 256-bit key encrypting Message.contents, shared by producers and the
+/// consumers reading their notifications out of RQueue. Priority, TTL and the
+/// signature fields stay in the clear, so RQueue can still route and
+/// authenticate messages it can't read.
+pub struct ContentKey {
+    key: [u8; 32],
+}
+
+impl fmt::Debug for ContentKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ContentKey(..)")
+    }
+}
+
+impl ContentKey {
+    /// Uses `key` as-is, for example one generated and distributed out of band.
+    pub fn new(key: [u8; 32]) -> ContentKey {
+        ContentKey { key }
+    }
+
+    /// Derives a key from a secret with HKDF-SHA256. Deriving with different
+    /// contexts, for example app names, gives unrelated keys from one secret.
+    pub fn derive(secret: &str, context: &str) -> ContentKey {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, secret.as_bytes())
+            .expand(format!("rqpush contents\n{}", context).as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        ContentKey { key }
+    }
+
+    /// Replaces the message contents with their encryption. Called before the
+    /// message is signed, so signatures and digests cover the ciphertext.
+    pub(crate) fn seal(&self, message: &mut Message) {
+        let encryption = Encryption::XChaCha20Poly1305V1;
+        let cipher = XChaCha20Poly1305::new((&self.key).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: message.contents.as_bytes(),
+            aad: encryption.label().as_bytes(),
+        };
+        let ciphertext = cipher.encrypt(&nonce, payload).expect("contents fit in one XChaCha20-Poly1305 message");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        message.contents = BASE64.encode(sealed);
+        message.encryption = Some(encryption);
+        trace!("rqpush seal: {} bytes", message.contents.len());
+    }
+
+    /// Decrypts the contents of an encrypted message. Verify the message's
+    /// signature first, as it covers the encrypted contents.
+    pub fn decrypt(&self, message: &Message) -> Result<String, VerifyError> {
+        let encryption = message.encryption.ok_or(VerifyError::NotEncrypted)?;
+        let sealed = BASE64.decode(&message.contents).map_err(|_| VerifyError::DecryptionFailed)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(VerifyError::DecryptionFailed);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = XChaCha20Poly1305::new((&self.key).into());
+        let payload = Payload {
+            msg: ciphertext,
+            aad: encryption.label().as_bytes(),
+        };
+        let contents = cipher.decrypt(XNonce::from_slice(nonce), payload).map_err(|_| VerifyError::DecryptionFailed)?;
+        String::from_utf8(contents).map_err(|e| VerifyError::MalformedContents(e.to_string()))
+    }
+
+    /// Decrypts the contents of an encrypted message and parses the notification.
+    pub fn open(&self, message: &Message) -> Result<OutboundNotification, VerifyError> {
+        let contents = self.decrypt(message)?;
+        serde_json::from_str(&contents).map_err(|e| VerifyError::MalformedContents(e.to_string()))
+    }
+}
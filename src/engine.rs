@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use handlebars::Handlebars;
+#[cfg(feature = "hot-reload")]
+use log::warn;
+use log::{debug, trace};
+use lru::LruCache;
+use serde_json::{Map, Value};
+
+use crate::{generate_sha256, helpers, template, Error};
+
+/// Default number of inline templates TemplateEngine keeps compiled.
+pub const DEFAULT_TEMPLATE_CACHE_CAPACITY: usize = 1024;
+
+/// Prefix of the names inline templates are registered under, followed by the
+/// sha256 of their source.
+const INLINE_PREFIX: &str = "sha256:";
+
+/// Suffixes of the files in a template directory, and the fields they render.
+const TEMPLATE_FILES: [(&str, &str); 3] = [(".title.hbs", "title"), (".text.hbs", "text"), (".html.hbs", "html")];
+
+#[derive(Clone)]
+/// A thread-safe Handlebars registry compiling each template once. Named
+/// templates stay registered until replaced, while inline templates, such as
+/// those set with `set_title_template`, are keyed by the sha256 of their source
+/// and the least recently used are dropped once the cache is full. Clones share
+/// the same templates. Every engine provides the `date`, `truncate`,
+/// `pluralize`, `join`, `upper` and `default` helpers, and the default
+/// templates in each language of template::GENERATED_BY_TRANSLATIONS.
+pub struct TemplateEngine {
+    registry: Arc<RwLock<Handlebars<'static>>>,
+    inline: Arc<Mutex<LruCache<String, ()>>>,
+    /// How many templates each set has, so notifications can check their set
+    /// exists without scanning the registry.
+    sets: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Default for TemplateEngine {
+    fn default() -> TemplateEngine {
+        TemplateEngine::new(DEFAULT_TEMPLATE_CACHE_CAPACITY)
+    }
+}
+
+impl TemplateEngine {
+    /// Creates an engine keeping up to `capacity` inline templates compiled.
+    pub fn new(capacity: usize) -> TemplateEngine {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        let mut registry = Handlebars::new();
+        helpers::register(&mut registry);
+        register_defaults(&mut registry);
+        TemplateEngine {
+            registry: Arc::new(RwLock::new(registry)),
+            inline: Arc::new(Mutex::new(LruCache::new(capacity))),
+            sets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The engine shared by every Notification.
+    pub fn global() -> &'static TemplateEngine {
+        static GLOBAL: OnceLock<TemplateEngine> = OnceLock::new();
+        GLOBAL.get_or_init(TemplateEngine::default)
+    }
+
+    /// Compiles `source` and registers it as `name`, replacing any template
+    /// previously registered with that name.
+    pub fn register_template(&self, name: &str, source: &str) -> Result<(), Error> {
+        debug!("rqpush registering template {}", name);
+        let mut registry = self.write();
+        let replaced = registry.has_template(name);
+        registry.register_template_string(name, source).map_err(|e| Error::TemplateCompile {
+            field: "template",
+            source: Box::new(e),
+        })?;
+        if !replaced {
+            let mut sets = self.lock_sets();
+            for set in template_sets(name) {
+                *sets.entry(set.to_string()).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers a helper computing a value from its parameters and hash, for
+    /// example `format_mac` used as `{{format_mac address sep=":"}}`, replacing
+    /// any helper, including a built-in one, previously registered as `name`.
+    /// Errors the helper returns fail the render.
+    pub fn register_helper<F>(&self, name: &str, helper: F)
+    where
+        F: Fn(&[Value], &Map<String, Value>) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> + Send + Sync + 'static,
+    {
+        debug!("rqpush registering helper {}", name);
+        self.write().register_helper(name, Box::new(helpers::CustomHelper(helper)));
+    }
+
+    /// Compiles `source` and registers it as the partial `name`, included in
+    /// templates with `{{> name}}`.
+    pub fn register_partial(&self, name: &str, source: &str) -> Result<(), Error> {
+        debug!("rqpush registering partial {}", name);
+        self.write().register_partial(name, source).map_err(|e| Error::TemplateCompile {
+            field: "partial",
+            source: Box::new(e),
+        })
+    }
+
+    /// Loads the notification templates in `dir`, returning how many were
+    /// loaded. Files are named `<name>.title.hbs`, `<name>.text.hbs` and
+    /// `<name>.html.hbs`, and are used by notifications that `set_template`
+    /// to `<name>`. A set may leave out any of the files, which then default
+    /// to the built-in templates. Templates for a language are named with the
+    /// language code after the name, ie `<name>.pt-BR.text.hbs`, see
+    /// Notification::set_lang. Other files are ignored.
+    pub fn load_dir<P: AsRef<Path>>(&self, dir: P) -> Result<usize, Error> {
+        let dir = dir.as_ref();
+        let io_error = |e| Error::Io {
+            context: format!("reading template directory {}", dir.display()),
+            source: e,
+        };
+        let mut loaded = 0;
+        for entry in fs::read_dir(dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            if self.load_file(&path)? {
+                loaded += 1;
+            }
+        }
+        debug!("rqpush loaded {} templates from {}", loaded, dir.display());
+        Ok(loaded)
+    }
+
+    /// Loads a single template file following the load_dir naming convention,
+    /// returning false if it doesn't follow it.
+    fn load_file(&self, path: &Path) -> Result<bool, Error> {
+        let name = match template_name(path) {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+        let source = fs::read_to_string(path).map_err(|e| Error::Io {
+            context: format!("reading template {}", path.display()),
+            source: e,
+        })?;
+        self.register_template(&name, &source)?;
+        Ok(true)
+    }
+
+    /// Loads the notification templates in `dir` as load_dir does, then keeps
+    /// watching it, recompiling templates as they're changed and unregistering
+    /// them when they're removed. Templates that fail to compile are logged and
+    /// the previous version kept. Watching stops when the returned
+    /// TemplateWatcher is dropped.
+    #[cfg(feature = "hot-reload")]
+    pub fn watch_dir<P: AsRef<Path>>(&self, dir: P) -> Result<TemplateWatcher, Error> {
+        use notify::{RecursiveMode, Watcher};
+
+        let dir = dir.as_ref();
+        self.load_dir(dir)?;
+        let watch_error = |e: notify::Error| Error::Io {
+            context: format!("watching template directory {}", dir.display()),
+            source: std::io::Error::other(e),
+        };
+        let engine = self.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                for path in &event.paths {
+                    engine.reload_file(path);
+                }
+            }
+            Err(e) => warn!("rqpush template watcher failed: {}", e),
+        })
+        .map_err(watch_error)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+        debug!("rqpush watching templates in {}", dir.display());
+        Ok(TemplateWatcher { _watcher: watcher })
+    }
+
+    /// Reloads a changed template file, or unregisters it if it was removed.
+    #[cfg(feature = "hot-reload")]
+    fn reload_file(&self, path: &Path) {
+        let name = match template_name(path) {
+            Some(name) => name,
+            None => return,
+        };
+        if !path.exists() {
+            debug!("rqpush unregistering removed template {}", &name);
+            let mut registry = self.write();
+            if registry.has_template(&name) {
+                registry.unregister_template(&name);
+                let mut sets = self.lock_sets();
+                for set in template_sets(&name) {
+                    if let Some(count) = sets.get_mut(set) {
+                        *count -= 1;
+                        if *count == 0 {
+                            sets.remove(set);
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        match self.load_file(path) {
+            Ok(_) => debug!("rqpush reloaded template {}", &name),
+            Err(e) => warn!("rqpush keeping previous template {}: {}", &name, e),
+        }
+    }
+
+    /// Whether a template is registered as `name`.
+    pub fn has_template(&self, name: &str) -> bool {
+        self.read().has_template(name)
+    }
+
+    /// Renders the template registered as `name`.
+    pub fn render(&self, name: &str, values: &Value) -> Result<String, Error> {
+        self.read().render(name, values).map_err(|e| Error::TemplateRender {
+            field: "template",
+            source: Box::new(e),
+        })
+    }
+
+    /// Renders an inline template, compiling it on first use. The `field` being
+    /// rendered is included in any error.
+    pub(crate) fn render_inline(&self, field: &'static str, source: &str, values: &Value) -> Result<String, Error> {
+        let name = format!("{}{}", INLINE_PREFIX, generate_sha256(source, None));
+        let render_error = |e| Error::TemplateRender {
+            field,
+            source: Box::new(e),
+        };
+        {
+            // Templates are only evicted under the write lock, so this one can't
+            // disappear before it's rendered.
+            let registry = self.read();
+            if registry.has_template(&name) {
+                self.lock_inline().get(&name);
+                return registry.render(&name, values).map_err(render_error);
+            }
+        }
+        let mut registry = self.write();
+        if !registry.has_template(&name) {
+            trace!("rqpush compiling inline template {}", &name);
+            registry.register_template_string(&name, source).map_err(|e| Error::TemplateCompile {
+                field,
+                source: Box::new(e),
+            })?;
+            if let Some((evicted, ())) = self.lock_inline().push(name.clone(), ()) {
+                if evicted != name {
+                    registry.unregister_template(&evicted);
+                }
+            }
+        }
+        registry.render(&name, values).map_err(render_error)
+    }
+
+    /// Renders `field` of a notification with the `part` template of set `set`
+    /// in the first of `langs` the set has one in, then with the set's
+    /// unlocalized template, then in the default language, or returns None if
+    /// the set doesn't include one.
+    pub(crate) fn render_set(&self, field: &'static str, set: &str, part: &str, langs: &[String], values: &Value) -> Option<Result<String, Error>> {
+        let registry = self.read();
+        let localized = |lang: &str| format!("{}.{}.{}", set, lang, part);
+        let name = langs
+            .iter()
+            .map(|lang| localized(lang))
+            .chain(std::iter::once(format!("{}.{}", set, part)))
+            .chain(std::iter::once(localized(template::DEFAULT_LANG)))
+            .find(|name| registry.has_template(name))?;
+        trace!("rqpush rendering {} with template {}", field, &name);
+        Some(registry.render(&name, values).map_err(|e| Error::TemplateRender {
+            field,
+            source: Box::new(e),
+        }))
+    }
+
+    /// Whether any template of set `set` is registered, in any language.
+    pub(crate) fn has_set(&self, set: &str) -> bool {
+        self.lock_sets().contains_key(set)
+    }
+
+    /// How many inline templates are compiled.
+    pub fn cached_templates(&self) -> usize {
+        self.lock_inline().len()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Handlebars<'static>> {
+        self.registry.read().expect("template registry poisoned")
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Handlebars<'static>> {
+        self.registry.write().expect("template registry poisoned")
+    }
+
+    fn lock_sets(&self) -> std::sync::MutexGuard<'_, HashMap<String, usize>> {
+        self.sets.lock().expect("template sets poisoned")
+    }
+
+    fn lock_inline(&self) -> std::sync::MutexGuard<'_, LruCache<String, ()>> {
+        self.inline.lock().expect("template cache poisoned")
+    }
+}
+
+/// Watches a template directory, see TemplateEngine::watch_dir.
+#[cfg(feature = "hot-reload")]
+pub struct TemplateWatcher {
+    _watcher: notify::RecommendedWatcher,
+}
+
+/// Registers the default templates of every language they're translated to, as
+/// set template::DEFAULT_TEMPLATE_SET.
+fn register_defaults(registry: &mut Handlebars) {
+    for (lang, generated_by) in template::GENERATED_BY_TRANSLATIONS.iter() {
+        let defaults = [
+            ("title", template::DEFAULT_TITLE_TEMPLATE),
+            ("text", template::DEFAULT_TEXT_TEMPLATE),
+            ("html", template::DEFAULT_HTML_TEMPLATE),
+        ];
+        for (part, source) in defaults.iter() {
+            let name = format!("{}.{}.{}", template::DEFAULT_TEMPLATE_SET, lang, part);
+            registry
+                .register_template_string(&name, source.replace(template::GENERATED_BY, generated_by))
+                .expect("default templates compile");
+        }
+    }
+}
+
+/// The languages to look for templates in, from most to least specific, ie
+/// "pt-BR", "pt" for "pt-BR".
+pub(crate) fn lang_chain(lang: &str) -> Vec<String> {
+    let mut chain = Vec::new();
+    let mut lang = lang.trim();
+    while !lang.is_empty() {
+        chain.push(lang.to_string());
+        lang = match lang.rfind(['-', '_']) {
+            Some(end) => &lang[..end],
+            None => "",
+        };
+    }
+    chain
+}
+
+/// The sets a template named like a set template belongs to, ie "welcome.pt"
+/// and "welcome" for "welcome.pt.title", as the language is optional.
+fn template_sets(name: &str) -> impl Iterator<Item = &str> {
+    let stem = TEMPLATE_FILES
+        .iter()
+        .find_map(|(_, part)| name.strip_suffix(part)?.strip_suffix('.'))
+        .unwrap_or_default();
+    stem.match_indices('.').map(move |(end, _)| &stem[..end]).chain(Some(stem).filter(|stem| !stem.is_empty()))
+}
+
+/// The name a template file is registered as, ie "welcome.title" for
+/// "welcome.title.hbs".
+fn template_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    TEMPLATE_FILES.iter().find_map(|(suffix, part)| {
+        let set = file_name.strip_suffix(suffix)?;
+        if set.is_empty() {
+            return None;
+        }
+        Some(format!("{}.{}", set, part))
+    })
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::{FanoutReport, RejectionKind};
+
+/// Errors that can occur while building, rendering or sending a notification.
+#[derive(Debug)]
+pub enum Error {
+    /// A notification field is missing or invalid, for example an empty app name.
+    InvalidInput {
+        /// The offending field.
+        field: &'static str,
+        /// Why the value was rejected.
+        reason: String,
+    },
+    /// A handlebars template could not be compiled.
+    TemplateCompile {
+        /// The notification field the template was rendering.
+        field: &'static str,
+        /// The underlying handlebars error.
+        source: Box<handlebars::TemplateError>,
+    },
+    /// A handlebars template compiled, but could not be rendered.
+    TemplateRender {
+        /// The notification field the template was rendering.
+        field: &'static str,
+        /// The underlying handlebars error.
+        source: Box<handlebars::RenderError>,
+    },
+    /// A value could not be serialized to or deserialized from JSON.
+    Serialization {
+        /// What was being serialized or deserialized.
+        context: &'static str,
+        /// The underlying serde_json error.
+        source: serde_json::Error,
+    },
+    /// A local I/O operation failed.
+    Io {
+        /// What was being attempted.
+        context: String,
+        /// The underlying I/O error.
+        source: std::io::Error,
+    },
+    /// An SRV service name could not be resolved to any RQueue server.
+    Resolve {
+        /// The service name being resolved.
+        name: String,
+        /// Why resolution failed.
+        reason: String,
+    },
+    /// The HTTP request to RQueue failed, or the HTTP client could not be built.
+    Transport {
+        /// The RQueue server being contacted.
+        server: String,
+        /// The underlying reqwest error.
+        source: reqwest::Error,
+    },
+    /// RQueue responded with a non-2xx status.
+    Rejected {
+        /// The RQueue server that rejected the notification.
+        server: String,
+        /// The HTTP status code returned.
+        status: u16,
+        /// The likely cause of the rejection.
+        kind: RejectionKind,
+        /// The response body, which may explain the rejection.
+        body: String,
+    },
+    /// Too few fanout destinations accepted the notification to satisfy the
+    /// fanout policy. The report holds the outcome for each destination.
+    Fanout(Box<FanoutReport>),
+    /// A bulk request failed after RQueue may have processed it, so its
+    /// notifications weren't resent individually. Each holds the same error.
+    Bulk(Arc<Error>),
+    /// The notification couldn't be delivered, but was saved to the spool for
+    /// later redelivery.
+    Spooled {
+        /// The spool entry holding the notification.
+        path: PathBuf,
+        /// Why delivery failed.
+        source: Box<Error>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidInput { field, reason } => write!(f, "invalid {}: {}", field, reason),
+            Error::TemplateCompile { field, source } => write!(f, "failed to compile {} template: {}", field, source),
+            Error::TemplateRender { field, source } => write!(f, "failed to render {} template: {}", field, source),
+            Error::Serialization { context, source } => write!(f, "failed to serialize {}: {}", context, source),
+            Error::Io { context, source } => write!(f, "failed {}: {}", context, source),
+            Error::Resolve { name, reason } => write!(f, "failed to resolve {}: {}", name, reason),
+            Error::Transport { server, source } => write!(f, "failed to send to {}: {}", server, source),
+            Error::Rejected { server, status, kind, body } => {
+                write!(f, "{} rejected notification with status {} ({:?}): {}", server, status, kind, body)
+            }
+            Error::Fanout(report) => write!(
+                f,
+                "only {} of {} fanout destinations accepted notification, policy {:?}",
+                report.successes(),
+                report.outcomes.len(),
+                report.policy
+            ),
+            Error::Bulk(source) => write!(f, "bulk send failed, notification may have been delivered: {}", source),
+            Error::Spooled { path, source } => write!(f, "{}, spooled to {}", source, path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidInput { .. } | Error::Resolve { .. } | Error::Rejected { .. } | Error::Fanout(_) => None,
+            Error::TemplateCompile { source, .. } => Some(source.as_ref()),
+            Error::TemplateRender { source, .. } => Some(source.as_ref()),
+            Error::Serialization { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            Error::Transport { source, .. } => Some(source),
+            Error::Bulk(source) => Some(source.as_ref()),
+            Error::Spooled { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
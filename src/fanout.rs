@@ -0,0 +1,72 @@
+use crate::{Error, SendReceipt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How many destinations must accept a fanned out notification for the send
+/// to succeed.
+pub enum FanoutPolicy {
+    /// Every destination must accept it.
+    All,
+    /// At least one destination must accept it.
+    Any,
+    /// At least this many destinations must accept it.
+    Quorum(usize),
+}
+
+#[derive(Debug, Clone)]
+/// Delivers one notification to several RQueue servers, for redundancy.
+pub struct Fanout {
+    pub(crate) servers: Vec<String>,
+    pub(crate) policy: FanoutPolicy,
+    pub(crate) parallel: bool,
+}
+
+impl Fanout {
+    /// Creates a fanout to `servers` requiring every server to accept the
+    /// notification, delivering to one server at a time.
+    pub fn new(servers: &[&str]) -> Fanout {
+        Fanout {
+            servers: servers.iter().map(|s| s.to_string()).collect(),
+            policy: FanoutPolicy::All,
+            parallel: false,
+        }
+    }
+
+    /// How many servers must accept the notification (otherwise will default
+    /// to FanoutPolicy::All).
+    pub fn policy(mut self, policy: FanoutPolicy) -> Fanout {
+        self.policy = policy;
+        self
+    }
+
+    /// Deliver to all servers concurrently rather than one at a time.
+    pub fn parallel(mut self, parallel: bool) -> Fanout {
+        self.parallel = parallel;
+        self
+    }
+}
+
+#[derive(Debug)]
+/// The outcome of delivering a notification to each fanout destination.
+pub struct FanoutReport {
+    /// The policy the outcomes were judged by.
+    pub policy: FanoutPolicy,
+    /// Each server, in the order configured, with the result of delivering to it.
+    pub outcomes: Vec<(String, Result<SendReceipt, Error>)>,
+}
+
+impl FanoutReport {
+    /// Number of servers that accepted the notification.
+    pub fn successes(&self) -> usize {
+        self.outcomes.iter().filter(|(_, r)| r.is_ok()).count()
+    }
+
+    /// Returns true if enough servers accepted the notification to satisfy the policy.
+    pub fn is_success(&self) -> bool {
+        let successes = self.successes();
+        match self.policy {
+            FanoutPolicy::All => successes == self.outcomes.len(),
+            FanoutPolicy::Any => successes > 0,
+            FanoutPolicy::Quorum(quorum) => successes >= quorum,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason, ScopedJson};
+use serde_json::{Map, Value};
+
+/// Format used by the date helper when none is given.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+/// Wraps a function computing a value from the helper's parameters, so its
+/// result can be used by other helpers as well as rendered.
+struct ValueHelper(fn(&Helper) -> Result<Value, RenderError>);
+
+impl HelperDef for ValueHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        (self.0)(h).map(ScopedJson::Derived)
+    }
+}
+
+/// Adapts a helper registered with TemplateEngine::register_helper, passing
+/// it the values of its parameters and hash.
+pub(crate) struct CustomHelper<F>(pub(crate) F);
+
+impl<F> HelperDef for CustomHelper<F>
+where
+    F: Fn(&[Value], &Map<String, Value>) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> + Send + Sync,
+{
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let params: Vec<Value> = h.params().iter().map(|p| p.value().clone()).collect();
+        let hash: Map<String, Value> = h.hash().iter().map(|(k, v)| (k.to_string(), v.value().clone())).collect();
+        (self.0)(&params, &hash)
+            .map(ScopedJson::Derived)
+            .map_err(|e| RenderErrorReason::Other(format!("helper {} failed: {}", h.name(), e)).into())
+    }
+}
+
+/// Registers the built-in helpers. They return values rather than writing
+/// output, so they can be nested, as in `{{join (truncate devices 3)}}`.
+pub(crate) fn register(registry: &mut Handlebars) {
+    registry.register_helper("date", Box::new(ValueHelper(date)));
+    registry.register_helper("truncate", Box::new(ValueHelper(truncate)));
+    registry.register_helper("pluralize", Box::new(ValueHelper(pluralize)));
+    registry.register_helper("join", Box::new(ValueHelper(join)));
+    registry.register_helper("upper", Box::new(ValueHelper(upper)));
+    registry.register_helper("default", Box::new(ValueHelper(default)));
+}
+
+/// The value of parameter `index`, null if it's missing from the context.
+fn param<'a>(h: &'a Helper, helper: &'static str, index: usize) -> Result<&'a Value, RenderError> {
+    h.param(index).map(|p| p.value()).ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex(helper, index).into())
+}
+
+/// A value as it would be rendered, with null as an empty string.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `{{date value "%Y-%m-%d"}}` formats a unix timestamp or RFC 3339 date in
+/// UTC (otherwise will default to "%Y-%m-%d %H:%M:%S UTC").
+fn date(h: &Helper) -> Result<Value, RenderError> {
+    let format = match h.param(1) {
+        Some(format) => format.value().as_str().ok_or(RenderErrorReason::InvalidParamType("date format string"))?,
+        None => DEFAULT_DATE_FORMAT,
+    };
+    let time = match param(h, "date", 0)? {
+        Value::Number(n) => n.as_i64().and_then(|seconds| DateTime::from_timestamp(seconds, 0)),
+        Value::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc)),
+        _ => None,
+    }
+    .ok_or(RenderErrorReason::InvalidParamType("unix timestamp or RFC 3339 date"))?;
+    let mut formatted = String::new();
+    write!(formatted, "{}", time.format(format)).map_err(|_| RenderErrorReason::Other(format!("invalid date format {:?}", format)))?;
+    Ok(Value::String(formatted))
+}
+
+/// `{{truncate value 40}}` keeps the first 40 characters of a string,
+/// appending "…" or `suffix="..."` if any were cut, or the first 40 items of
+/// an array.
+fn truncate(h: &Helper) -> Result<Value, RenderError> {
+    let value = param(h, "truncate", 0)?;
+    let length = param(h, "truncate", 1)?.as_u64().ok_or(RenderErrorReason::InvalidParamType("truncate length"))? as usize;
+    match value {
+        Value::Array(items) => Ok(Value::Array(items.iter().take(length).cloned().collect())),
+        Value::Null => Ok(Value::Null),
+        other => {
+            let text = text(other);
+            if text.chars().count() <= length {
+                return Ok(Value::String(text));
+            }
+            let suffix = h.hash_get("suffix").and_then(|s| s.value().as_str()).unwrap_or("…");
+            Ok(Value::String(text.chars().take(length).chain(suffix.chars()).collect()))
+        }
+    }
+}
+
+/// `{{pluralize count "device"}}` gives "1 device" or "2 devices", or
+/// `{{pluralize count "person" "people"}}` for irregular plurals. An array
+/// counts its items.
+fn pluralize(h: &Helper) -> Result<Value, RenderError> {
+    let count = match param(h, "pluralize", 0)? {
+        Value::Array(items) => items.len().to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => return Err(RenderErrorReason::InvalidParamType("pluralize count").into()),
+    };
+    let singular = text(param(h, "pluralize", 1)?);
+    let word = if count == "1" {
+        singular
+    } else {
+        match h.param(2) {
+            Some(plural) => text(plural.value()),
+            None => format!("{}s", singular),
+        }
+    };
+    Ok(Value::String(format!("{} {}", count, word)))
+}
+
+/// `{{join items ", "}}` joins the items of an array (otherwise will default to
+/// joining with ", ").
+fn join(h: &Helper) -> Result<Value, RenderError> {
+    let separator = h.param(1).map(|s| text(s.value())).unwrap_or_else(|| ", ".to_string());
+    match param(h, "join", 0)? {
+        Value::Array(items) => Ok(Value::String(items.iter().map(text).collect::<Vec<_>>().join(&separator))),
+        other => Ok(Value::String(text(other))),
+    }
+}
+
+/// `{{upper value}}` uppercases a string.
+fn upper(h: &Helper) -> Result<Value, RenderError> {
+    Ok(Value::String(text(param(h, "upper", 0)?).to_uppercase()))
+}
+
+/// `{{default value "fallback"}}` gives the fallback if the value is missing,
+/// null or an empty string.
+fn default(h: &Helper) -> Result<Value, RenderError> {
+    match param(h, "default", 0)? {
+        Value::Null => Ok(param(h, "default", 1)?.clone()),
+        Value::String(s) if s.is_empty() => Ok(param(h, "default", 1)?.clone()),
+        value => Ok(value.clone()),
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use log::trace;
+
+use crate::{verify_message_hmac, Message, VerifyError};
+
+#[derive(Clone, Default, PartialEq, Eq)]
+/// Named HMAC shared secrets, allowing them to be rotated without downtime.
+/// Messages are signed with the current key and carry its id in
+/// Message.key_id, while receivers accept any key in the keyring. To rotate,
+/// receivers first accept the new key, then producers make it current, and
+/// finally receivers drop the old one.
+pub struct SecretKeyring {
+    current: Option<String>,
+    keys: HashMap<String, String>,
+    unnamed: Option<String>,
+}
+
+impl fmt::Debug for SecretKeyring {
+    /// Lists the key ids, never the secrets.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut key_ids: Vec<&String> = self.keys.keys().collect();
+        key_ids.sort();
+        f.debug_struct("SecretKeyring")
+            .field("current", &self.current)
+            .field("keys", &key_ids)
+            .field("unnamed", &self.unnamed.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl SecretKeyring {
+    /// Creates a keyring signing with `secret` as `key_id`.
+    pub fn new(key_id: &str, secret: &str) -> SecretKeyring {
+        SecretKeyring::default().current(key_id, secret)
+    }
+
+    /// Signs with `secret` as `key_id`, keeping any previous current key as
+    /// an accepted key.
+    pub fn current(mut self, key_id: &str, secret: &str) -> SecretKeyring {
+        self.keys.insert(key_id.to_string(), secret.to_string());
+        self.current = Some(key_id.to_string());
+        self
+    }
+
+    /// Accepts messages signed with `secret` as `key_id`, without signing with it.
+    pub fn accept(mut self, key_id: &str, secret: &str) -> SecretKeyring {
+        self.keys.insert(key_id.to_string(), secret.to_string());
+        self
+    }
+
+    /// Accepts messages with no key id signed with `secret`, as sent by
+    /// producers that predate key rotation. If no key has been made current,
+    /// messages are also signed with it.
+    pub fn accept_unnamed(mut self, secret: &str) -> SecretKeyring {
+        self.unnamed = Some(secret.to_string());
+        self
+    }
+
+    /// Stops accepting messages signed as `key_id`. The current key can't be
+    /// removed, returning false.
+    pub fn remove(&mut self, key_id: &str) -> bool {
+        if self.current.as_deref() == Some(key_id) {
+            return false;
+        }
+        self.keys.remove(key_id).is_some()
+    }
+
+    /// The id of the key messages are signed with, if any.
+    pub fn current_key_id(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// The key id and secret messages are signed with, or None if no key has
+    /// been made current and there's no unnamed secret.
+    pub(crate) fn signing_key(&self) -> Option<(Option<&str>, &str)> {
+        match &self.current {
+            Some(key_id) => Some((Some(key_id), &self.keys[key_id])),
+            None => self.unnamed.as_deref().map(|secret| (None, secret)),
+        }
+    }
+
+    /// Verifies the HMAC signature of a message, with the key named by its
+    /// Message.key_id.
+    pub fn verify(&self, message: &Message) -> Result<(), VerifyError> {
+        trace!("rqpush keyring verify: key_id({:?})", &message.key_id);
+        let secret = match &message.key_id {
+            Some(key_id) => self.keys.get(key_id).ok_or_else(|| VerifyError::UnknownKey(key_id.clone()))?,
+            None => self.unnamed.as_ref().ok_or(VerifyError::MissingKeyId)?,
+        };
+        verify_message_hmac(message, secret)
+    }
+}
+
+impl From<&str> for SecretKeyring {
+    /// A keyring holding only an unnamed shared secret.
+    fn from(secret: &str) -> SecretKeyring {
+        SecretKeyring::default().accept_unnamed(secret)
+    }
+}
@@ -13,17 +13,54 @@ extern crate serde_derive;
 
 use std::result::Result;
 
-use handlebars::Handlebars;
-use log::{debug, error, trace};
-use reqwest::{Error, Response};
+use log::{debug, trace};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 
+pub use client::{RqueueClient, RqueueClientBuilder, DEFAULT_BATCH_CONCURRENCY, DEFAULT_PRIORITY, DEFAULT_TTL};
+pub use ed25519::{load_signing_key, load_verifying_key, Ed25519Keyring};
+pub use ed25519_dalek::{SigningKey as Ed25519SigningKey, VerifyingKey as Ed25519VerifyingKey};
+pub use encryption::{ContentKey, Encryption};
+#[cfg(feature = "hot-reload")]
+pub use engine::TemplateWatcher;
+pub use engine::{TemplateEngine, DEFAULT_TEMPLATE_CACHE_CAPACITY};
+pub use error::Error;
+pub use fanout::{Fanout, FanoutPolicy, FanoutReport};
+pub use keyring::SecretKeyring;
+pub use receipt::{RejectionKind, SendReceipt};
+pub use retry::RetryPolicy;
+pub use router::{Matcher, Route, Router};
+pub use signing::{generate_hmac_sha256, verify_hmac_sha256, verify_message_hmac, SignatureVersion, Signer};
+pub use verify::{LruNonceStore, NonceStore, ReplayVerifier, VerifyError, DEFAULT_MAX_SKEW, DEFAULT_NONCE_CAPACITY};
+pub use spool::{FlushReport, Spool, SpoolEntry};
+#[cfg(feature = "dns")]
+pub use srv::DnsSrvLookup;
+pub use srv::{LookupFuture, SrvLookup, SrvRecords, SrvResolver, SrvTarget, StaticSrvTable, DEFAULT_STATIC_TTL};
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod client;
+mod ed25519;
+mod encryption;
+mod engine;
+mod error;
+mod fanout;
+mod helpers;
+mod keyring;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+mod receipt;
+mod retry;
+mod router;
+mod signing;
+mod spool;
+mod srv;
 mod template;
+mod verify;
 #[cfg(test)]
 mod tests;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// An object used to generate notifications.
 pub struct Notification {
     /// Name of application generating notification.
@@ -38,6 +75,11 @@ pub struct Notification {
     pub lang: String,
     /// Title of the notification.
     pub title: String,
+    /// Optional priority, overriding the default of the RqueueClient sending it.
+    pub priority: Option<u8>,
+    /// Optional TTL in seconds, overriding the default of the RqueueClient sending it.
+    pub ttl: Option<u32>,
+    template: Option<String>,
     title_template: Option<String>,
     short_text: String,
     short_text_template: Option<String>,
@@ -55,22 +97,43 @@ impl Notification {
     ///  - `app` is the app name
     ///  - `title` is short text for the notification (ie, an email subject)
     ///  - `short_text` is longer text for the notification (ie, an email body)
+    ///
+    /// Required fields are validated when the notification is sent, use `try_init`
+    /// to validate them immediately.
     pub fn init(app: &str, title: &str, short_text: &str) -> Notification {
         trace!("rqpush init: app({}) title({}) short_text({})", &app, &title, &short_text);
         let default_values = match serde_json::from_str(template::DEFAULT_MAPPING) {
             Ok(v) => v,
-            Err(e) => {
-                error!("error in init(): {}", e);
-                json!(null)
-            }
+            // DEFAULT_MAPPING is a constant, so this can only be a bug in rqpush.
+            Err(e) => panic!("invalid template::DEFAULT_MAPPING: {}", e),
         };
+        Notification::with_values(app, title, short_text, default_values)
+    }
+
+    /// Initializes a notification like `init`, returning an error if any of the
+    /// required fields are empty.
+    pub fn try_init(app: &str, title: &str, short_text: &str) -> Result<Notification, Error> {
+        trace!("rqpush try_init: app({}) title({}) short_text({})", &app, &title, &short_text);
+        let default_values = serde_json::from_str(template::DEFAULT_MAPPING).map_err(|e| Error::Serialization {
+            context: "template::DEFAULT_MAPPING",
+            source: e,
+        })?;
+        let notification = Notification::with_values(app, title, short_text, default_values);
+        notification.validate()?;
+        Ok(notification)
+    }
+
+    fn with_values(app: &str, title: &str, short_text: &str, default_values: Value) -> Notification {
         Notification {
             app: app.to_string(),
             url: None,
             tagline: None,
             category: None,
-            lang: default_values["lang"].to_string(),
+            lang: default_values["lang"].as_str().unwrap_or(template::DEFAULT_LANG).to_string(),
             title: title.to_string(),
+            priority: None,
+            ttl: None,
+            template: None,
             title_template: None,
             short_text: short_text.to_string(),
             short_text_template: None,
@@ -84,6 +147,27 @@ impl Notification {
         }
     }
 
+    /// Confirms all required fields are set.
+    fn validate(&self) -> Result<(), Error> {
+        for (field, value) in &[("app", &self.app), ("title", &self.title), ("short_text", &self.short_text)] {
+            if value.trim().is_empty() {
+                return Err(Error::InvalidInput {
+                    field,
+                    reason: "must not be empty".to_string(),
+                });
+            }
+        }
+        if let Some(set) = &self.template {
+            if !TemplateEngine::global().has_set(set) {
+                return Err(Error::InvalidInput {
+                    field: "template",
+                    reason: format!("no templates named {} are loaded", set),
+                });
+            }
+        }
+        Ok(())
+    }
+
     /// Update the notification object, setting the notification app name.
     pub fn set_app(&mut self, app: &str) -> &Notification {
         trace!("rqpush set_app: app({})", &app);
@@ -116,7 +200,11 @@ impl Notification {
         self
     }
 
-    /// Update the notification object, setting the notification language.
+    /// Update the notification object, setting the notification language, ie
+    /// "fr" or "pt-BR". Templates are looked up in the language, then without
+    /// each subtag, then without a language, then in template::DEFAULT_LANG, so
+    /// "pt-BR" uses "pt" templates if there are no "pt-BR" ones, and "en" ones
+    /// if none of those exist.
     pub fn set_lang(&mut self, lang: &str) -> &Notification {
         trace!("rqpush set_lang: lang({})", &lang);
         self.lang = lang.to_string();
@@ -132,8 +220,33 @@ impl Notification {
         self
     }
 
+    /// Update the notification object, setting the priority used when sent with an
+    /// RqueueClient (otherwise will default to the client's priority).
+    pub fn set_priority(&mut self, priority: u8) -> &Notification {
+        trace!("rqpush set_priority: priority({})", priority);
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Update the notification object, setting the ttl used when sent with an
+    /// RqueueClient (otherwise will default to the client's ttl).
+    pub fn set_ttl(&mut self, ttl: u32) -> &Notification {
+        trace!("rqpush set_ttl: ttl({})", ttl);
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Update the notification object, rendering it with the templates loaded as
+    /// `name` by TemplateEngine::load_dir. Templates set on the notification
+    /// itself take precedence, and any the set leaves out use the defaults.
+    pub fn set_template(&mut self, name: &str) -> &Notification {
+        trace!("rqpush set_template: template({})", &name);
+        self.template = Some(name.to_string());
+        self
+    }
+
     /// Update the notification object, setting the title_template (otherwise will
-    /// default to template::DEFAULT_TITLE_TEMPLATE).
+    /// default to the title template of the notification's language).
     pub fn set_title_template(&mut self, template: String) -> &Notification {
         trace!("rqpush set_title_template: title_template({})", &template);
         self.title_template = Some(template.to_string());
@@ -148,7 +261,7 @@ impl Notification {
     }
 
     /// Update the notification object, setting the short_text_template (otherwise will
-    /// default to template::DEFAULT_TEXT_TEMPLATE).
+    /// default to the text template of the notification's language).
     pub fn set_short_text_template(&mut self, template: String) -> &Notification {
         trace!("rqpush set_text_template: text_template({})", &template);
         self.short_text_template = Some(template.to_string());
@@ -172,7 +285,7 @@ impl Notification {
     }
 
     /// Update the notification object, setting the long_text_template (otherwise will
-    /// default to template::DEFAULT_TEXT_TEMPLATE).
+    /// default to the text template of the notification's language).
     pub fn set_long_text_template(&mut self, template: String) -> &Notification {
         trace!("rqpush set_long_text_template: long_text_template({})", &template);
         self.long_text_template = Some(template.to_string());
@@ -188,7 +301,7 @@ impl Notification {
     }
 
     /// Update the notification object, setting the short_html_template (otherwise will
-    /// default to template::DEFAULT_HTML_TEMPLATE).
+    /// default to the html template of the notification's language).
     pub fn set_short_html_template(&mut self, template: String) -> &Notification {
         trace!("rqpush set_short_html_template: short_html_template({})", &template);
         self.short_html_template = Some(template.to_string());
@@ -196,7 +309,7 @@ impl Notification {
     }
 
     /// Update the notification object, setting the long_html_template (otherwise will
-    /// default to template::DEFAULT_HTML_TEMPLATE).
+    /// default to the html template of the notification's language).
     pub fn set_long_html_template(&mut self, template: String) -> &Notification {
         trace!("rqpush set_long_html_template: long_html_template({})", &template);
         self.long_html_template = Some(template.to_string());
@@ -223,144 +336,225 @@ impl Notification {
 
     /// Compiles and sends the notification. Any missing fields are automatically
     /// filled out, a sha256 is calculated (salted with an optional shared secret),
-    /// then the notification is sent using Reqwest.
+    /// then the notification is sent using a temporary blocking RqueueClient.
+    /// When sending many notifications, reuse an RqueueClient instead. Use
+    /// `render` or `build_message` to preview what would be sent.
+    #[cfg(feature = "blocking")]
     pub fn send(
-        &mut self,
+        &self,
         server: &str,
         priority: u8,
         ttl: u32,
         shared_secret: Option<&str>,
-    ) -> Result<Response, Error> {
+    ) -> Result<SendReceipt, Error> {
         trace!("rqpush send: server({}) priority({}) ttl({}) shared_secret({:?})", &server, priority, ttl, &shared_secret);
+        let client = temporary_client(server, priority, ttl, shared_secret).build_blocking()?;
+        let message = self.compile(server, priority, ttl, &Signer::Legacy(shared_secret.map(String::from)))?;
+        client.deliver(server, &message)
+    }
+
+    /// Compiles and sends the notification without blocking the calling thread.
+    /// Behaves exactly like `send`, but returns a future.
+    pub async fn send_async(
+        &self,
+        server: &str,
+        priority: u8,
+        ttl: u32,
+        shared_secret: Option<&str>,
+    ) -> Result<SendReceipt, Error> {
+        trace!("rqpush send_async: server({}) priority({}) ttl({}) shared_secret({:?})", &server, priority, ttl, &shared_secret);
+        let client = temporary_client(server, priority, ttl, shared_secret).build()?;
+        let message = self.compile(server, priority, ttl, &Signer::Legacy(shared_secret.map(String::from)))?;
+        client.deliver(server, &message).await
+    }
+
+    /// Fills out any missing fields and processes all templates, returning the
+    /// OutboundNotification that would be sent, without sending it. Priority and
+    /// TTL default to DEFAULT_PRIORITY and DEFAULT_TTL if not set. Useful for
+    /// previewing notifications and debugging templates.
+    pub fn render(&self) -> Result<OutboundNotification, Error> {
+        self.render_with(self.priority.unwrap_or(DEFAULT_PRIORITY), self.ttl.unwrap_or(DEFAULT_TTL))
+    }
+
+    /// Renders the notification as `render` does and wraps it in a Message, with
+    /// a sha256 salted with an optional shared secret. This is exactly what
+    /// `send` delivers.
+    pub fn build_message(&self, shared_secret: Option<&str>) -> Result<Message, Error> {
+        let outbound_notification = self.render()?;
+        let priority = outbound_notification.priority;
+        into_message(outbound_notification, priority, None, &Signer::Legacy(shared_secret.map(String::from)))
+    }
+
+    fn render_with(&self, priority: u8, ttl: u32) -> Result<OutboundNotification, Error> {
+        self.validate()?;
+        let mut values = self.values.clone();
+
         // Provide field mappings, ie {{app}} and {{category}}
-        if !&self.values["app"].is_null() {
-            self.values["app"] = json!(&self.app);
-        }
-        if !&self.values["url"].is_null() {
-            self.values["url"] = json!(&self.url);
-        }
-        if !&self.values["category"].is_null() {
-            self.values["category"] = json!(&self.category);
-        }
+        values["app"] = json!(&self.app);
+        values["url"] = json!(&self.url);
+        values["category"] = json!(&self.category);
 
         // Create the final outbound notification object
-        let mut outbound_notification = OutboundNotification::default();
-        outbound_notification.app = self.app.clone();
-        outbound_notification.lang = self.lang.clone();
-        outbound_notification.priority = priority;
-        outbound_notification.ttl = ttl;
+        let mut outbound_notification = OutboundNotification {
+            app: self.app.clone(),
+            lang: self.lang.clone(),
+            priority,
+            ttl,
+            ..Default::default()
+        };
 
         // Process title (which may include {{variables}})
-        self.title_template = match &self.title_template {
-            Some(tt) => Some(tt.to_string()),
-            None => Some(template::DEFAULT_TITLE_TEMPLATE.to_string()),
-        };
-        outbound_notification.title = process_template(
+        outbound_notification.title = self.render_field(
+            "title",
             self.title.clone(),
-            self.title_template.clone().unwrap(),
-            &mut self.values,
-        );
-        if !&self.values["title"].is_null() {
-            self.values["title"] = json!(&outbound_notification.title);
-        }
-        // Update self.title as well to make information available to library users
-        self.title = outbound_notification.title.clone();
+            self.title_template.as_deref(),
+            template::DEFAULT_TITLE_TEMPLATE,
+            &mut values,
+        )?;
+        values["title"] = json!(&outbound_notification.title);
 
         // If url isn't set manually, set to empty string
-        outbound_notification.url = match &self.url {
-            Some(u) => u.to_string(),
-            None => "".to_string(),
-        };
+        outbound_notification.url = self.url.clone().unwrap_or_default();
 
         // If category isn't set manually, set to empty string
-        outbound_notification.category = match &self.category {
-            Some(c) => c.to_string(),
-            None => "".to_string(),
-        };
+        outbound_notification.category = self.category.clone().unwrap_or_default();
 
         // If tagline isn't set manually, set it to app name
         outbound_notification.tagline = match &self.tagline {
             Some(t) => t.to_string(),
             None => self.app.clone(),
         };
-        if !&self.values["tagline"].is_null() {
-            self.values["tagline"] = json!(&outbound_notification.tagline);
-        }
+        values["tagline"] = json!(&outbound_notification.tagline);
 
         // Process short_text (which may include {{variables}})
-        self.short_text_template = match &self.short_text_template {
-            Some(stt) => Some(stt.to_string()),
-            None => Some(template::DEFAULT_TEXT_TEMPLATE.to_string()),
-        };
-        outbound_notification.short_text = process_template(
+        outbound_notification.short_text = self.render_field(
+            "short_text",
             self.short_text.clone(),
-            self.short_text_template.clone().unwrap(),
-            &mut self.values,
-        );
+            self.short_text_template.as_deref(),
+            template::DEFAULT_TEXT_TEMPLATE,
+            &mut values,
+        )?;
 
         // If custom html isn't provided, use the text version, then process
-        outbound_notification.short_html = match &self.short_html {
-            Some(sh) => sh.to_string(),
-            None => self.short_text.clone(),
-        };
-        self.short_html_template = match &self.short_html_template {
-            Some(sht) => Some(sht.to_string()),
-            None => Some(template::DEFAULT_HTML_TEMPLATE.to_string()),
-        };
-        outbound_notification.short_html = process_template(
-            outbound_notification.short_html.clone(),
-            self.short_html_template.clone().unwrap(),
-            &mut self.values,
-        );
+        let short_html = self.short_html.clone().unwrap_or_else(|| self.short_text.clone());
+        outbound_notification.short_html = self.render_field(
+            "short_html",
+            short_html,
+            self.short_html_template.as_deref(),
+            template::DEFAULT_HTML_TEMPLATE,
+            &mut values,
+        )?;
 
         // If custom long text isn't provided, use the short text version
-        self.long_text = match &self.long_text {
-            Some(lt) => Some(lt.to_string()),
-            None => Some(self.short_text.clone()),
-        };
-        self.long_text_template = match &self.long_text_template {
-            Some(ltt) => Some(ltt.to_string()),
-            None => Some(template::DEFAULT_TEXT_TEMPLATE.to_string()),
-        };
-        outbound_notification.long_text = process_template(
-            outbound_notification.long_text.clone(),
-            self.long_text_template.clone().unwrap(),
-            &mut self.values,
-        );
+        let long_text = self.long_text.clone().unwrap_or_else(|| self.short_text.clone());
+        outbound_notification.long_text = self.render_field(
+            "long_text",
+            long_text.clone(),
+            self.long_text_template.as_deref(),
+            template::DEFAULT_TEXT_TEMPLATE,
+            &mut values,
+        )?;
 
         // If custom html isn't provided, use the text version
-        outbound_notification.long_html = match &self.long_html {
-            Some(lh) => lh.to_string(),
-            None => self.long_text.clone().unwrap(),
-        };
-        self.long_html_template = match &self.long_html_template {
-            Some(lht) => Some(lht.to_string()),
-            None => Some(template::DEFAULT_HTML_TEMPLATE.to_string()),
-        };
-        outbound_notification.long_html = process_template(
-            outbound_notification.long_html.clone(),
-            self.long_html_template.clone().unwrap(),
-            &mut self.values,
-        );
-
-        let contents = json!(outbound_notification).to_string();
-        let sha256 = Some(generate_sha256(&contents, shared_secret));
-        debug!("rqpush sending message '{}' with priority of {}, sha256 of {:?} and ttl of {} to {}...", &outbound_notification.title, priority, &sha256, ttl, &server);
-
-        let message = Message {
-            sha256: sha256,
-            contents: contents,
-            priority: Some(priority),
-            ttl: Some(outbound_notification.ttl),
+        let long_html = self.long_html.clone().unwrap_or(long_text);
+        outbound_notification.long_html = self.render_field(
+            "long_html",
+            long_html,
+            self.long_html_template.as_deref(),
+            template::DEFAULT_HTML_TEMPLATE,
+            &mut values,
+        )?;
+
+        trace!("rqpush render: outbound_notification({:?})", &outbound_notification);
+        Ok(outbound_notification)
+    }
+
+    /// Renders one field with its own template if set, otherwise with the
+    /// notification's named template set, otherwise with the default template,
+    /// the latter two in the notification's language if available.
+    fn render_field(
+        &self,
+        field: &'static str,
+        notification: String,
+        template: Option<&str>,
+        default: &str,
+        values: &mut Value,
+    ) -> Result<String, Error> {
+        if let Some(template) = template {
+            return process_template(field, notification, template.to_string(), values);
+        }
+        let part = match field {
+            "title" => "title",
+            "short_text" | "long_text" => "text",
+            _ => "html",
         };
-        trace!("rqpush send: message({:?})", &message);
+        let langs = engine::lang_chain(&self.lang);
+        values["notification"] = json!(&notification);
+        let sets = self.template.iter().map(String::as_str).chain(std::iter::once(template::DEFAULT_TEMPLATE_SET));
+        for set in sets {
+            if let Some(rendered) = TemplateEngine::global().render_set(field, set, part, &langs, values) {
+                return rendered;
+            }
+        }
+        process_template(field, notification, default.to_string(), values)
+    }
 
-        let client = reqwest::Client::new();
-        client.post(server).json(&message).send()
+    /// Renders the notification and wraps the resulting OutboundNotification in
+    /// a Message signed by `signer`.
+    pub(crate) fn compile(
+        &self,
+        server: &str,
+        priority: u8,
+        ttl: u32,
+        signer: &Signer,
+    ) -> Result<Message, Error> {
+        self.compile_sealed(server, priority, ttl, None, signer)
+    }
+
+    /// As compile, encrypting the contents with `content_key` if set before the
+    /// message is signed.
+    pub(crate) fn compile_sealed(
+        &self,
+        server: &str,
+        priority: u8,
+        ttl: u32,
+        content_key: Option<&ContentKey>,
+        signer: &Signer,
+    ) -> Result<Message, Error> {
+        let outbound_notification = self.render_with(priority, ttl)?;
+        debug!("rqpush sending message '{}' with priority of {} and ttl of {} to {}...", &outbound_notification.title, priority, ttl, &server);
+        into_message(outbound_notification, priority, content_key, signer)
+    }
+}
+
+/// Wraps a rendered notification in a Message, encrypting the contents with
+/// `content_key` if set, then signing it.
+fn into_message(
+    outbound_notification: OutboundNotification,
+    priority: u8,
+    content_key: Option<&ContentKey>,
+    signer: &Signer,
+) -> Result<Message, Error> {
+    signer.validate()?;
+    let contents = serde_json::to_string(&outbound_notification).map_err(|e| Error::Serialization {
+        context: "OutboundNotification",
+        source: e,
+    })?;
+    let mut message = Message {
+        contents,
+        priority: Some(priority),
+        ttl: Some(outbound_notification.ttl),
+        ..Default::default()
+    };
+    if let Some(content_key) = content_key {
+        content_key.seal(&mut message);
     }
+    signer.sign(&mut message);
+    trace!("rqpush into_message: message({:?})", &message);
+    Ok(message)
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 /// The final outbound notification object that is derived from
 /// the internal Notification object. This is delivered inside
 /// Message.contents.
@@ -391,7 +585,7 @@ pub struct OutboundNotification {
     pub priority: u8,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 /// The final outbound message that is sent, where "contents" is the OutboundNotification
 /// derived from the internal Notification.
 pub struct Message {
@@ -401,37 +595,64 @@ pub struct Message {
     pub contents: String,
     /// Value from 0-255, higher number is higher priority.
     pub priority: Option<u8>,
+    /// When the message was signed, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Random value unique to this message, allowing replays to be detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// How "signature" was generated, unset for legacy messages that are only
+    /// authenticated by a salted "sha256".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_version: Option<SignatureVersion>,
+    /// Hex encoded signature of the "contents", and for newer signature versions
+    /// the "timestamp" and "nonce".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// How "contents" was encrypted, unset when it's plaintext.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// Identifies the key that generated the "signature", when the sender has
+    /// more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
     /// How long the notification is valid, in seconds.
     pub ttl: Option<u32>,
 }
 
+/// Configures the single-use client behind Notification::send and send_async.
+fn temporary_client(server: &str, priority: u8, ttl: u32, shared_secret: Option<&str>) -> RqueueClientBuilder {
+    let builder = RqueueClient::builder(server).priority(priority).ttl(ttl);
+    match shared_secret {
+        Some(s) => builder.shared_secret(s),
+        None => builder,
+    }
+}
+
 /// Generate a sha256 of a string, including an optional shared_secret as salt.
 pub fn generate_sha256(text: &str, shared_secret: Option<&str>) -> String {
     trace!("rqpush generate_sha256: text({}) shared_secret({:?})", &text, &shared_secret);
     let mut hasher = Sha256::new();
-    hasher.input(text.as_bytes());
-    let salt = match shared_secret {
-        Some(s) => s,
-        None => "",
-    };
-    if salt != "" {
-        hasher.input(salt.as_bytes());
+    hasher.update(text.as_bytes());
+    let salt = shared_secret.unwrap_or_default();
+    if !salt.is_empty() {
+        hasher.update(salt.as_bytes());
     }
-    let sha = format!("{:x}", hasher.result());
+    let sha = format!("{:x}", hasher.finalize());
     trace!("rqpush generate_sha256: sha256({})", &sha);
     sha
 }
 
-/// Invokes handlebars to convert {{tokens}} to their values.
-fn process_template(notification: String, template: String, values: &mut Value) -> String {
-    trace!("rqpush process_template: notification({}) template({}) values({:?})", &notification, &template, &values);
+/// Invokes handlebars to convert {{tokens}} to their values, compiling the
+/// template once in the global TemplateEngine. The `field` being rendered is
+/// included in any error.
+fn process_template(
+    field: &'static str,
+    notification: String,
+    template: String,
+    values: &mut Value,
+) -> Result<String, Error> {
+    trace!("rqpush process_template: field({}) notification({}) template({}) values({:?})", field, &notification, &template, &values);
     values["notification"] = json!(notification);
-    let handlebars = Handlebars::new();
-    match handlebars.render_template(&template, &values) {
-        Ok(h) => h,
-        Err(e) => {
-            error!("error in process_html_template: {}", e);
-            "".to_string()
-        }
-    }
+    TemplateEngine::global().render_inline(field, &template, values)
 }
@@ -0,0 +1,260 @@
+//! An in-process stand-in for RQueue, for testing code that sends
+//! notifications. Enabled by the `test-support` feature.
+//!
+//! The server listens on an ephemeral localhost port, records every Message it
+//! receives and verifies its digest, answering 401 when the digest is wrong.
+//! Responses can be scripted to test retries, timeouts and rejections.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, trace};
+
+use crate::{Error, Message, VerifyError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How the mock server answers a request.
+pub struct MockResponse {
+    status: u16,
+    body: String,
+    delay: Duration,
+    disconnect: bool,
+}
+
+impl Default for MockResponse {
+    fn default() -> MockResponse {
+        MockResponse::status(200).body(r#"{"status": "queued"}"#)
+    }
+}
+
+impl MockResponse {
+    /// Answers with `status` and an empty body.
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            body: String::new(),
+            delay: Duration::from_secs(0),
+            disconnect: false,
+        }
+    }
+
+    /// Closes the connection without answering, as a crashed server would.
+    pub fn disconnect() -> MockResponse {
+        MockResponse {
+            disconnect: true,
+            ..MockResponse::status(0)
+        }
+    }
+
+    /// The response body.
+    pub fn body(mut self, body: &str) -> MockResponse {
+        self.body = body.to_string();
+        self
+    }
+
+    /// How long to wait before answering (otherwise will default to 0).
+    pub fn delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = delay;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A Message received by the mock server.
+pub struct Received {
+    /// The request path, for example "/" or a bulk path.
+    pub path: String,
+    /// The message as received.
+    pub message: Message,
+    /// Whether its digest, and signature if any, matched the server's secret.
+    pub verified: Result<(), VerifyError>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    shared_secret: Option<String>,
+    script: VecDeque<MockResponse>,
+    fallback: MockResponse,
+    received: Vec<Received>,
+    requests: usize,
+}
+
+/// A mock RQueue server, shut down when dropped.
+pub struct MockRqueue {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    stopping: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl MockRqueue {
+    /// Starts a server accepting unsalted legacy messages.
+    pub fn start() -> Result<MockRqueue, Error> {
+        MockRqueue::start_with_secret(None)
+    }
+
+    /// Starts a server verifying messages with `shared_secret`, as legacy
+    /// salted digests or HMAC signatures.
+    pub fn start_with_secret(shared_secret: Option<&str>) -> Result<MockRqueue, Error> {
+        let io_error = |source| Error::Io {
+            context: "starting mock RQueue".to_string(),
+            source,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(io_error)?;
+        let addr = listener.local_addr().map_err(io_error)?;
+        let state = Arc::new(Mutex::new(MockState {
+            shared_secret: shared_secret.map(String::from),
+            ..Default::default()
+        }));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let state = state.clone();
+            let stopping = stopping.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let state = state.clone();
+                        thread::spawn(move || handle_connection(stream, &state));
+                    }
+                }
+            })
+        };
+        debug!("rqpush mock RQueue listening on {}", addr);
+        Ok(MockRqueue {
+            addr,
+            state,
+            stopping,
+            listener: Some(handle),
+        })
+    }
+
+    /// The URL to send notifications to.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Queues responses to use, in order, for the next requests.
+    pub fn respond(&self, responses: &[MockResponse]) {
+        self.lock().script.extend(responses.iter().cloned());
+    }
+
+    /// The response used once scripted responses run out (otherwise will
+    /// default to 200 with a JSON body).
+    pub fn set_fallback(&self, response: MockResponse) {
+        self.lock().fallback = response;
+    }
+
+    /// Every message received so far, in order.
+    pub fn received(&self) -> Vec<Received> {
+        self.lock().received.clone()
+    }
+
+    /// Every message received so far whose digest was verified.
+    pub fn messages(&self) -> Vec<Message> {
+        self.lock().received.iter().filter(|r| r.verified.is_ok()).map(|r| r.message.clone()).collect()
+    }
+
+    /// How many requests have been answered, including failed ones.
+    pub fn requests(&self) -> usize {
+        self.lock().requests
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock RQueue state poisoned")
+    }
+}
+
+impl Drop for MockRqueue {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake the listener so it notices it's stopping.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.listener.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Answers a single request, closing the connection afterwards.
+fn handle_connection(mut stream: TcpStream, state: &Mutex<MockState>) {
+    let (path, body) = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
+    trace!("rqpush mock RQueue request: path({}) body({})", &path, &body);
+    let response = {
+        let mut state = state.lock().expect("mock RQueue state poisoned");
+        state.requests += 1;
+        let messages: Vec<Message> = match serde_json::from_str(&body) {
+            Ok(messages) => messages,
+            Err(_) => serde_json::from_str(&body).map(|m| vec![m]).unwrap_or_default(),
+        };
+        let mut verified = !messages.is_empty();
+        for message in messages {
+            let result = message.verify(state.shared_secret.as_deref());
+            verified &= result.is_ok();
+            state.received.push(Received {
+                path: path.clone(),
+                message,
+                verified: result,
+            });
+        }
+        match state.script.pop_front() {
+            Some(response) => response,
+            None if !verified => MockResponse::status(401).body(r#"{"error": "sha256 mismatch"}"#),
+            None => state.fallback.clone(),
+        }
+    };
+    thread::sleep(response.delay);
+    if response.disconnect {
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    let reply = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    );
+    let _ = stream.write_all(reply.as_bytes());
+}
+
+/// Reads the path and body of an HTTP/1.1 request with a Content-Length.
+fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let path = head.lines().next()?.split_whitespace().nth(1)?.to_string();
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while buffer.len() < header_end + length {
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    Some((path, String::from_utf8_lossy(&buffer[header_end..header_end + length]).to_string()))
+}
@@ -0,0 +1,64 @@
+use log::{debug, trace};
+use serde_json::Value;
+
+use crate::{Error, Message};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Confirmation that RQueue accepted a notification.
+pub struct SendReceipt {
+    /// The RQueue server that accepted the notification.
+    pub server: String,
+    /// The 2xx HTTP status code returned.
+    pub status: u16,
+    /// The sha256 of the message that was accepted.
+    pub sha256: Option<String>,
+    /// The JSON response body, if RQueue returned one.
+    pub body: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why RQueue rejected a notification, derived from the response.
+pub enum RejectionKind {
+    /// The sha256 didn't match, usually because of a wrong shared secret.
+    Auth,
+    /// The message was malformed or no longer valid, for example an expired TTL.
+    Validation,
+    /// RQueue failed or is overloaded, retrying later may succeed.
+    Server,
+}
+
+impl RejectionKind {
+    /// Classifies a non-2xx response from its status code and body.
+    pub fn classify(status: u16, body: &str) -> RejectionKind {
+        match status {
+            401 | 403 => RejectionKind::Auth,
+            429 | 500..=599 => RejectionKind::Server,
+            // RQueue reports digest mismatches as generic client errors.
+            _ if body.to_lowercase().contains("sha256") => RejectionKind::Auth,
+            _ => RejectionKind::Validation,
+        }
+    }
+}
+
+/// Turns RQueue's response to `message` into a SendReceipt, or Error::Rejected
+/// if the status isn't 2xx.
+pub(crate) fn interpret_response(server: &str, message: &Message, status: u16, body: String) -> Result<SendReceipt, Error> {
+    trace!("rqpush interpret_response: server({}) status({}) body({})", &server, status, &body);
+    if !(200..300).contains(&status) {
+        let kind = RejectionKind::classify(status, &body);
+        debug!("rqpush message rejected by {} with status {} ({:?})", &server, status, kind);
+        return Err(Error::Rejected {
+            server: server.to_string(),
+            status,
+            kind,
+            body,
+        });
+    }
+    Ok(SendReceipt {
+        server: server.to_string(),
+        status,
+        sha256: message.sha256.clone(),
+        // RQueue doesn't always return a body, and it isn't always JSON.
+        body: serde_json::from_str(&body).ok(),
+    })
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Controls how an RqueueClient retries notifications that failed to send.
+/// Delays grow exponentially from `base_delay`, doubling with each attempt up
+/// to `max_delay`, and are randomly shortened by up to `jitter` so that many
+/// producers don't retry in lockstep. Retries are never scheduled past the
+/// notification's TTL, as RQueue would drop it anyway.
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    retry_connect: bool,
+    retry_timeout: bool,
+    retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    /// Up to 3 attempts, starting with a 100ms delay, retrying connection
+    /// failures, timeouts and 429, 502, 503 and 504 responses.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_connect: true,
+            retry_timeout: true,
+            retry_statuses: vec![429, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries, which is what clients use unless configured.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy::default().max_attempts(1)
+    }
+
+    /// Total number of attempts, including the first; 1 disables retries.
+    pub fn max_attempts(mut self, max_attempts: u32) -> RetryPolicy {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry.
+    pub fn base_delay(mut self, base_delay: Duration) -> RetryPolicy {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Upper bound for the delay between attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> RetryPolicy {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Fraction of each delay, from 0.0 to 1.0, that is randomly removed.
+    pub fn jitter(mut self, jitter: f64) -> RetryPolicy {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Whether failures to connect to RQueue are retried.
+    pub fn retry_connect(mut self, retry: bool) -> RetryPolicy {
+        self.retry_connect = retry;
+        self
+    }
+
+    /// Whether requests that timed out are retried.
+    pub fn retry_timeout(mut self, retry: bool) -> RetryPolicy {
+        self.retry_timeout = retry;
+        self
+    }
+
+    /// HTTP statuses returned by RQueue that are retried.
+    pub fn retry_statuses(mut self, statuses: &[u16]) -> RetryPolicy {
+        self.retry_statuses = statuses.to_vec();
+        self
+    }
+
+    /// Returns true if another attempt is allowed after `attempt` attempts.
+    pub fn allows_attempt(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Returns true if `error` is worth retrying under this policy.
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Transport { source, .. } => {
+                (self.retry_connect && source.is_connect()) || (self.retry_timeout && source.is_timeout())
+            }
+            Error::Rejected { status, .. } => self.retry_statuses.contains(status),
+            _ => false,
+        }
+    }
+
+    /// Delay before the next attempt, after `attempt` attempts have failed.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay.checked_mul(1 << exponent).unwrap_or(self.max_delay).min(self.max_delay);
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
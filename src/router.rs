@@ -0,0 +1,197 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use log::trace;
+use regex::Regex;
+
+use crate::{Error, Notification};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+/// Matches a notification field. Patterns starting with `re:` are regular
+/// expressions, anything else is a glob such as `security_*`.
+pub enum Matcher {
+    /// A glob pattern.
+    Glob(glob::Pattern),
+    /// A regular expression.
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// Parses a glob, or a regular expression if prefixed with `re:`.
+    pub fn new(pattern: &str) -> Result<Matcher, Error> {
+        let invalid = |reason: String| Error::InvalidInput {
+            field: "route pattern",
+            reason: format!("{}: {}", pattern, reason),
+        };
+        match pattern.strip_prefix("re:") {
+            Some(regex) => Ok(Matcher::Regex(Regex::new(regex).map_err(|e| invalid(e.to_string()))?)),
+            None => Ok(Matcher::Glob(glob::Pattern::new(pattern).map_err(|e| invalid(e.to_string()))?)),
+        }
+    }
+
+    /// Returns true if `value` matches the pattern.
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Matcher::Glob(pattern) => pattern.matches(value),
+            Matcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl TryFrom<String> for Matcher {
+    type Error = Error;
+
+    fn try_from(pattern: String) -> Result<Matcher, Error> {
+        Matcher::new(&pattern)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+/// Sends notifications matching every configured pattern to `server`. A route
+/// without patterns matches all notifications.
+pub struct Route {
+    /// Pattern the notification category must match.
+    #[serde(default)]
+    pub category: Option<Matcher>,
+    /// Pattern the notification app must match.
+    #[serde(default)]
+    pub app: Option<Matcher>,
+    /// Pattern the notification language must match.
+    #[serde(default)]
+    pub lang: Option<Matcher>,
+    /// The RQueue server matching notifications are sent to.
+    pub server: String,
+    /// Priority for matching notifications that don't set their own.
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// TTL for matching notifications that don't set their own.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+}
+
+impl Route {
+    /// Creates a route to `server` matching all notifications.
+    pub fn new(server: &str) -> Route {
+        Route {
+            category: None,
+            app: None,
+            lang: None,
+            server: server.to_string(),
+            priority: None,
+            ttl: None,
+        }
+    }
+
+    /// Only match notifications whose category matches `pattern`. Notifications
+    /// without a category are matched as an empty string.
+    pub fn category(mut self, pattern: &str) -> Result<Route, Error> {
+        self.category = Some(Matcher::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Only match notifications whose app matches `pattern`.
+    pub fn app(mut self, pattern: &str) -> Result<Route, Error> {
+        self.app = Some(Matcher::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Only match notifications whose language matches `pattern`.
+    pub fn lang(mut self, pattern: &str) -> Result<Route, Error> {
+        self.lang = Some(Matcher::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Priority for matching notifications that don't set their own.
+    pub fn priority(mut self, priority: u8) -> Route {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// TTL for matching notifications that don't set their own.
+    pub fn ttl(mut self, ttl: u32) -> Route {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns true if the notification matches all of the route's patterns.
+    pub fn matches(&self, notification: &Notification) -> bool {
+        let matches = |matcher: &Option<Matcher>, value: &str| matcher.as_ref().is_none_or(|m| m.matches(value));
+        matches(&self.category, notification.category.as_deref().unwrap_or_default())
+            && matches(&self.app, &notification.app)
+            && matches(&self.lang, &notification.lang)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+/// Picks the RQueue server, priority and TTL for each notification from an
+/// ordered list of routes. The first matching route wins, then the fallback.
+///
+/// Routers can be loaded from TOML:
+///
+/// ```toml
+/// [[route]]
+/// category = "security_*"
+/// server = "http://security.example.com:8000"
+/// priority = 200
+///
+/// [[route]]
+/// app = "re:^(netgrasp|nmap)$"
+/// lang = "en"
+/// server = "http://network.example.com:8000"
+/// ttl = 3600
+///
+/// [fallback]
+/// server = "http://rqueue.example.com:8000"
+/// ```
+pub struct Router {
+    #[serde(default, rename = "route")]
+    routes: Vec<Route>,
+    #[serde(default)]
+    fallback: Option<Route>,
+}
+
+impl Router {
+    /// Creates a router without any routes.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Parses a router from a TOML string.
+    pub fn from_toml(config: &str) -> Result<Router, Error> {
+        toml::from_str(config).map_err(|e| Error::InvalidInput {
+            field: "router config",
+            reason: e.to_string(),
+        })
+    }
+
+    /// Loads a router from a TOML file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Router, Error> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).map_err(|e| Error::Io {
+            context: format!("reading router config {}", path.display()),
+            source: e,
+        })?;
+        Router::from_toml(&config)
+    }
+
+    /// Appends a route, checked after all routes added before it.
+    pub fn add_route(&mut self, route: Route) -> &mut Router {
+        self.routes.push(route);
+        self
+    }
+
+    /// Sets the route used when no other route matches.
+    pub fn set_fallback(&mut self, route: Route) -> &mut Router {
+        self.fallback = Some(route);
+        self
+    }
+
+    /// Returns the first route matching the notification, or the fallback.
+    pub fn route(&self, notification: &Notification) -> Option<&Route> {
+        let route = self.routes.iter().find(|r| r.matches(notification)).or(self.fallback.as_ref());
+        trace!("rqpush route: app({}) category({:?}) route({:?})", &notification.app, &notification.category, &route);
+        route
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+
+use ed25519_dalek::{Signer as _, SigningKey};
+use hmac::{Hmac, Mac};
+use log::{trace, warn};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::spool::unix_now;
+use crate::{generate_sha256, Error, Message, SecretKeyring, VerifyError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Identifies how Message.signature was generated, so RQueue can tell the
+/// signing schemes apart.
+pub enum SignatureVersion {
+    /// HMAC-SHA256 of the contents, keyed with the shared secret.
+    #[serde(rename = "hmac-sha256-v1")]
+    HmacSha256V1,
+    /// HMAC-SHA256 of the timestamp, nonce and contents, keyed with the shared
+    /// secret, so captured messages can't be replayed.
+    #[serde(rename = "hmac-sha256-v2")]
+    HmacSha256V2,
+    /// Ed25519 signature of the timestamp, nonce and contents, made with the
+    /// private key identified by Message.key_id.
+    #[serde(rename = "ed25519-v1")]
+    Ed25519V1,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+/// How messages are authenticated before being sent.
+pub enum Signer {
+    /// The original scheme: only Message.sha256 is set, to the sha256 of the
+    /// contents with the optional shared secret appended. Understood by all
+    /// RQueue versions, but offers no replay protection.
+    Legacy(Option<String>),
+    /// Message.timestamp and Message.nonce are set, and Message.signature to an
+    /// HMAC-SHA256 covering them and the contents, keyed with the shared secret.
+    /// Message.sha256 is set to the unsalted sha256 of the contents.
+    HmacSha256(String),
+    /// As HmacSha256, keyed with the keyring's current secret. Message.key_id
+    /// is set to its id, so receivers know which secret to verify with.
+    Keyring(SecretKeyring),
+    /// As HmacSha256, but Message.signature is an Ed25519 signature made with
+    /// the app's private key, and Message.key_id is set so receivers can pick
+    /// the public key to verify it with. Receivers don't need to hold any
+    /// secret, so one app can't forge another's notifications.
+    Ed25519 {
+        /// Identifies the key to receivers, see Ed25519Keyring.
+        key_id: String,
+        /// The app's private key.
+        key: Box<SigningKey>,
+    },
+}
+
+impl fmt::Debug for Signer {
+    /// Shows the variant and key id, never the secret or private key.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Signer::Legacy(None) => write!(f, "Legacy(None)"),
+            Signer::Legacy(Some(_)) => write!(f, "Legacy(Some(..))"),
+            Signer::HmacSha256(_) => write!(f, "HmacSha256(..)"),
+            Signer::Keyring(keyring) => f.debug_tuple("Keyring").field(keyring).finish(),
+            Signer::Ed25519 { key_id, .. } => f.debug_struct("Ed25519").field("key_id", key_id).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Default for Signer {
+    fn default() -> Signer {
+        Signer::Legacy(None)
+    }
+}
+
+impl Signer {
+    /// Creates an Ed25519 signer, see load_signing_key for reading `key` from
+    /// a file.
+    pub fn ed25519(key_id: &str, key: SigningKey) -> Signer {
+        Signer::Ed25519 {
+            key_id: key_id.to_string(),
+            key: Box::new(key),
+        }
+    }
+
+    /// Checks the signer can sign messages, ie a keyring has a signing key.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self {
+            Signer::Keyring(keyring) if keyring.signing_key().is_none() => Err(Error::InvalidInput {
+                field: "signer",
+                reason: "keyring has no current key or unnamed secret".to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the digest and signature fields of `message` from its contents.
+    pub fn sign(&self, message: &mut Message) {
+        match self {
+            Signer::Legacy(shared_secret) => {
+                message.sha256 = Some(generate_sha256(&message.contents, shared_secret.as_deref()));
+                message.signature_version = None;
+                message.signature = None;
+                message.key_id = None;
+            }
+            Signer::HmacSha256(shared_secret) => sign_hmac(message, None, shared_secret),
+            Signer::Keyring(keyring) => match keyring.signing_key() {
+                Some((key_id, shared_secret)) => sign_hmac(message, key_id, shared_secret),
+                // Leave the message unsigned, so receivers reject it, rather than
+                // signing it with an empty secret.
+                None => {
+                    warn!("rqpush sign: keyring has no signing key");
+                    message.sha256 = None;
+                    message.signature_version = None;
+                    message.signature = None;
+                    message.key_id = None;
+                }
+            },
+            Signer::Ed25519 { key_id, key } => {
+                message.sha256 = Some(generate_sha256(&message.contents, None));
+                message.timestamp = Some(unix_now());
+                message.nonce = Some(generate_nonce());
+                message.signature_version = Some(SignatureVersion::Ed25519V1);
+                message.signature = Some(hex::encode(key.sign(signed_payload(message).as_bytes()).to_bytes()));
+                message.key_id = Some(key_id.clone());
+            }
+        }
+        trace!("rqpush sign: sha256({:?}) signature({:?})", &message.sha256, &message.signature);
+    }
+}
+
+fn sign_hmac(message: &mut Message, key_id: Option<&str>, shared_secret: &str) {
+    message.sha256 = Some(generate_sha256(&message.contents, None));
+    message.timestamp = Some(unix_now());
+    message.nonce = Some(generate_nonce());
+    message.signature_version = Some(SignatureVersion::HmacSha256V2);
+    message.signature = Some(generate_hmac_sha256(&signed_payload(message), shared_secret));
+    message.key_id = key_id.map(String::from);
+}
+
+/// The text covered by the message's signature, which depends on its
+/// signature version.
+pub(crate) fn signed_payload(message: &Message) -> String {
+    match message.signature_version {
+        Some(SignatureVersion::HmacSha256V2) | Some(SignatureVersion::Ed25519V1) => format!(
+            "{}\n{}\n{}",
+            message.timestamp.unwrap_or_default(),
+            message.nonce.as_deref().unwrap_or_default(),
+            &message.contents
+        ),
+        _ => message.contents.clone(),
+    }
+}
+
+/// Verifies the HMAC signature of a message signed by Signer::HmacSha256, with
+/// either signature version.
+pub fn verify_message_hmac(message: &Message, shared_secret: &str) -> Result<(), VerifyError> {
+    let signature = message.signature.as_deref().ok_or(VerifyError::MissingSignature)?;
+    match message.signature_version {
+        Some(SignatureVersion::HmacSha256V1) | Some(SignatureVersion::HmacSha256V2) => (),
+        version => return Err(VerifyError::UnsupportedSignature(version)),
+    }
+    if verify_hmac_sha256(&signed_payload(message), shared_secret, signature) {
+        Ok(())
+    } else {
+        Err(VerifyError::SignatureMismatch)
+    }
+}
+
+/// Generate a random hex encoded 128-bit nonce.
+fn generate_nonce() -> String {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    hex::encode(nonce)
+}
+
+/// Generate a hex encoded HMAC-SHA256 of a string, keyed with shared_secret.
+pub fn generate_hmac_sha256(text: &str, shared_secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(shared_secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(text.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Verify a hex encoded HMAC-SHA256 generated by generate_hmac_sha256. The
+/// comparison is constant time.
+pub fn verify_hmac_sha256(text: &str, shared_secret: &str, signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(shared_secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(text.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
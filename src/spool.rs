@@ -0,0 +1,203 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, trace, warn};
+
+use crate::{Error, Message};
+
+/// Extension of entries claimed by a flush while they're redelivered.
+const INFLIGHT_EXTENSION: &str = "inflight";
+
+/// Distinguishes entries spooled within the same nanosecond.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A message that could not be delivered, along with where and when it was
+/// originally sent.
+pub struct SpoolEntry {
+    /// The RQueue server the message was sent to.
+    pub server: String,
+    /// When the message was first sent, in seconds since the Unix epoch.
+    pub created: u64,
+    /// The undelivered message.
+    pub message: Message,
+}
+
+impl SpoolEntry {
+    /// Seconds until RQueue would drop the message, None if it never expires.
+    pub fn remaining_ttl(&self, now: u64) -> Option<u32> {
+        match self.message.ttl {
+            Some(ttl) if ttl > 0 => {
+                let expires = self.created + u64::from(ttl);
+                Some(expires.saturating_sub(now) as u32)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if the message's TTL has elapsed.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.remaining_ttl(now) == Some(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The outcome of flushing a spool.
+pub struct FlushReport {
+    /// Entries successfully redelivered and removed.
+    pub delivered: usize,
+    /// Entries removed because their TTL had elapsed.
+    pub expired: usize,
+    /// Entries removed because RQueue permanently rejected them.
+    pub rejected: usize,
+    /// Entries left in the spool to retry later.
+    pub remaining: usize,
+}
+
+#[derive(Debug, Clone)]
+/// A directory of undelivered messages, stored one JSON file per SpoolEntry so
+/// they survive process restarts.
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    /// Opens the spool in `dir`, creating the directory if necessary.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Spool, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| io_error("creating spool directory", &dir, e))?;
+        Ok(Spool { dir })
+    }
+
+    /// The directory holding spooled messages.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes `message` to the spool, returning the path of the new entry. Its
+    /// TTL counts from Message.timestamp (otherwise will default to now), use
+    /// `push_at` if it was first sent earlier.
+    pub fn push(&self, server: &str, message: &Message) -> Result<PathBuf, Error> {
+        self.push_at(server, message, message.timestamp.unwrap_or_else(unix_now))
+    }
+
+    /// Writes `message` to the spool as `push` does, counting its TTL from
+    /// `created`, in seconds since the Unix epoch, such as the time it was
+    /// first sent.
+    pub fn push_at(&self, server: &str, message: &Message, created: u64) -> Result<PathBuf, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let entry = SpoolEntry {
+            server: server.to_string(),
+            created,
+            message: message.clone(),
+        };
+        let contents = serde_json::to_vec(&entry).map_err(|e| Error::Serialization {
+            context: "SpoolEntry",
+            source: e,
+        })?;
+        // Names sort in creation order, and are written then renamed so a crash
+        // never leaves a partial entry behind.
+        let name = format!(
+            "{:020}-{:010}-{:06}",
+            now.as_nanos(),
+            std::process::id(),
+            SEQUENCE.fetch_add(1, Ordering::Relaxed) % 1_000_000
+        );
+        let temporary = self.dir.join(format!(".{}.tmp", name));
+        let path = self.dir.join(format!("{}.json", name));
+        fs::write(&temporary, contents).map_err(|e| io_error("writing spool entry", &temporary, e))?;
+        fs::rename(&temporary, &path).map_err(|e| io_error("writing spool entry", &path, e))?;
+        debug!("rqpush spooled message for {} to {}", server, path.display());
+        Ok(path)
+    }
+
+    /// Lists spooled entries, oldest first.
+    pub fn entries(&self) -> Result<Vec<(PathBuf, SpoolEntry)>, Error> {
+        let mut paths = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir).map_err(|e| io_error("reading spool directory", &self.dir, e))? {
+            let path = dir_entry.map_err(|e| io_error("reading spool directory", &self.dir, e))?.path();
+            if path.extension() == Some(OsStr::new("json")) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut entries = Vec::new();
+        for path in paths {
+            let contents = match fs::read(&path) {
+                Ok(contents) => contents,
+                // Claimed by another flush since the directory was read.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error("reading spool entry", &path, e)),
+            };
+            match serde_json::from_slice(&contents) {
+                Ok(entry) => entries.push((path, entry)),
+                // A corrupt entry shouldn't block the rest of the spool.
+                Err(e) => warn!("rqpush skipping unreadable spool entry {}: {}", path.display(), e),
+            }
+        }
+        trace!("rqpush spool entries: {}", entries.len());
+        Ok(entries)
+    }
+
+    /// Removes an entry returned by `entries` or `claim`. Entries that are
+    /// already gone are treated as removed.
+    pub fn remove(&self, path: &Path) -> Result<(), Error> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io_error("removing spool entry", path, e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Claims an entry returned by `entries` before redelivering it, by renaming
+    /// it with an `.inflight` extension so other flushes, including those of
+    /// other processes sharing the directory, skip it. Returns the claimed path,
+    /// or None if another flush claimed or removed it first.
+    pub fn claim(&self, path: &Path) -> Result<Option<PathBuf>, Error> {
+        let claimed = path.with_extension(INFLIGHT_EXTENSION);
+        match fs::rename(path, &claimed) {
+            Ok(()) => Ok(Some(claimed)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error("claiming spool entry", path, e)),
+        }
+    }
+
+    /// Returns a claimed entry to the spool, to be retried by a later flush.
+    pub fn release(&self, claimed: &Path) -> Result<PathBuf, Error> {
+        let path = claimed.with_extension("json");
+        fs::rename(claimed, &path).map_err(|e| io_error("releasing spool entry", claimed, e))?;
+        Ok(path)
+    }
+
+    /// Returns every claimed entry to the spool, returning how many there were.
+    /// Entries stay claimed if a process exits while redelivering them, so call
+    /// this at startup when no other process is flushing the spool.
+    pub fn release_all(&self) -> Result<usize, Error> {
+        let mut released = 0;
+        for dir_entry in fs::read_dir(&self.dir).map_err(|e| io_error("reading spool directory", &self.dir, e))? {
+            let path = dir_entry.map_err(|e| io_error("reading spool directory", &self.dir, e))?.path();
+            if path.extension() == Some(OsStr::new(INFLIGHT_EXTENSION)) {
+                self.release(&path)?;
+                released += 1;
+            }
+        }
+        debug!("rqpush released {} claimed spool entries", released);
+        Ok(released)
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn io_error(action: &str, path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        context: format!("{} {}", action, path.display()),
+        source,
+    }
+}
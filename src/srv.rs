@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use rand::Rng;
+
+use crate::Error;
+
+/// How long StaticSrvTable results are cached, unless configured otherwise.
+pub const DEFAULT_STATIC_TTL: Duration = Duration::from_secs(300);
+
+/// The future returned by SrvLookup::lookup_srv.
+pub type LookupFuture<'a> = Pin<Box<dyn Future<Output = Result<SrvRecords, Error>> + Send + 'a>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single SRV record.
+pub struct SrvTarget {
+    /// Lower values are tried first.
+    pub priority: u16,
+    /// Relative chance of being picked among targets with the same priority.
+    pub weight: u16,
+    /// Port RQueue listens on.
+    pub port: u16,
+    /// Host RQueue runs on.
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The SRV records found for a service name, and how long they may be cached.
+pub struct SrvRecords {
+    /// The records found.
+    pub targets: Vec<SrvTarget>,
+    /// How long the records may be cached.
+    pub ttl: Duration,
+}
+
+/// Looks up SRV records. Implemented by StaticSrvTable, and by DnsSrvLookup
+/// when the `dns` feature is enabled.
+pub trait SrvLookup: fmt::Debug + Send + Sync {
+    /// Returns the SRV records for a service name such as `_rqueue._tcp.example.com`.
+    fn lookup_srv<'a>(&'a self, name: &'a str) -> LookupFuture<'a>;
+}
+
+impl<L: SrvLookup + ?Sized> SrvLookup for Arc<L> {
+    fn lookup_srv<'a>(&'a self, name: &'a str) -> LookupFuture<'a> {
+        (**self).lookup_srv(name)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A fixed table of SRV records, for testing or for networks without DNS.
+pub struct StaticSrvTable {
+    records: HashMap<String, Vec<SrvTarget>>,
+    ttl: Option<Duration>,
+}
+
+impl StaticSrvTable {
+    /// Creates an empty table.
+    pub fn new() -> StaticSrvTable {
+        StaticSrvTable::default()
+    }
+
+    /// Parses a hosts-style table, with one record per line in the form
+    /// `<name> <priority> <weight> <port> <target>`. Blank lines and anything
+    /// after a `#` are ignored.
+    pub fn parse(table: &str) -> Result<StaticSrvTable, Error> {
+        let mut static_table = StaticSrvTable::new();
+        for (number, line) in table.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason: &str| Error::InvalidInput {
+                field: "srv table",
+                reason: format!("line {}: {}", number + 1, reason),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(invalid("expected <name> <priority> <weight> <port> <target>"));
+            }
+            let number_field = |value: &str| value.parse::<u16>().map_err(|e| invalid(&e.to_string()));
+            static_table.insert(
+                fields[0],
+                SrvTarget {
+                    priority: number_field(fields[1])?,
+                    weight: number_field(fields[2])?,
+                    port: number_field(fields[3])?,
+                    target: fields[4].to_string(),
+                },
+            );
+        }
+        Ok(static_table)
+    }
+
+    /// Adds a record for `name`.
+    pub fn insert(&mut self, name: &str, target: SrvTarget) -> &mut StaticSrvTable {
+        self.records.entry(normalize(name)).or_default().push(target);
+        self
+    }
+
+    /// How long resolved records may be cached (otherwise will default to
+    /// DEFAULT_STATIC_TTL).
+    pub fn set_ttl(&mut self, ttl: Duration) -> &mut StaticSrvTable {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+impl SrvLookup for StaticSrvTable {
+    fn lookup_srv<'a>(&'a self, name: &'a str) -> LookupFuture<'a> {
+        let result = match self.records.get(&normalize(name)) {
+            Some(targets) => Ok(SrvRecords {
+                targets: targets.clone(),
+                ttl: self.ttl.unwrap_or(DEFAULT_STATIC_TTL),
+            }),
+            None => Err(resolve_error(name, "no SRV records in static table")),
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(feature = "dns")]
+#[derive(Clone)]
+/// Looks up SRV records in DNS, using the system resolver configuration.
+pub struct DnsSrvLookup {
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "dns")]
+impl fmt::Debug for DnsSrvLookup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsSrvLookup").finish()
+    }
+}
+
+#[cfg(feature = "dns")]
+impl DnsSrvLookup {
+    /// Creates a lookup using the system resolver configuration, ie /etc/resolv.conf.
+    pub fn from_system_conf() -> Result<DnsSrvLookup, Error> {
+        let resolver = hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| resolve_error("system configuration", &e.to_string()))?;
+        Ok(DnsSrvLookup { resolver })
+    }
+}
+
+#[cfg(feature = "dns")]
+impl SrvLookup for DnsSrvLookup {
+    fn lookup_srv<'a>(&'a self, name: &'a str) -> LookupFuture<'a> {
+        Box::pin(async move {
+            let lookup = self.resolver.srv_lookup(name).await.map_err(|e| resolve_error(name, &e.to_string()))?;
+            let ttl = lookup.as_lookup().valid_until().saturating_duration_since(Instant::now());
+            let targets = lookup
+                .iter()
+                .map(|srv| SrvTarget {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: srv.target().to_utf8(),
+                })
+                .collect();
+            Ok(SrvRecords { targets, ttl })
+        })
+    }
+}
+
+/// Resolved targets by service name, along with when they expire.
+type SrvCache = HashMap<String, (Instant, Vec<SrvTarget>)>;
+
+#[derive(Debug, Clone)]
+/// Resolves an SRV service name to an ordered list of RQueue URLs to try,
+/// caching lookups for as long as their TTL allows. Targets are ordered by
+/// priority, and randomly by weight within a priority, following RFC 2782.
+pub struct SrvResolver {
+    lookup: Arc<dyn SrvLookup>,
+    cache: Arc<Mutex<SrvCache>>,
+    scheme: String,
+    path: String,
+}
+
+impl SrvResolver {
+    /// Creates a resolver using `lookup` to find SRV records.
+    pub fn new<L: SrvLookup + 'static>(lookup: L) -> SrvResolver {
+        SrvResolver {
+            lookup: Arc::new(lookup),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            scheme: "http".to_string(),
+            path: "/".to_string(),
+        }
+    }
+
+    /// URL scheme used to contact targets (otherwise will default to "http").
+    pub fn scheme(mut self, scheme: &str) -> SrvResolver {
+        self.scheme = scheme.to_string();
+        self
+    }
+
+    /// URL path RQueue accepts notifications on (otherwise will default to "/").
+    pub fn path(mut self, path: &str) -> SrvResolver {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Returns the targets for `name`, in the order they should be tried.
+    pub async fn resolve(&self, name: &str) -> Result<Vec<SrvTarget>, Error> {
+        let key = normalize(name);
+        let cached = self.cache.lock().expect("srv cache poisoned").get(&key).cloned();
+        let targets = match cached {
+            Some((expires, targets)) if Instant::now() < expires => {
+                trace!("rqpush srv cache hit: name({})", &name);
+                targets
+            }
+            _ => {
+                let records = self.lookup.lookup_srv(name).await?;
+                debug!("rqpush resolved {} to {} targets, caching for {:?}", &name, records.targets.len(), records.ttl);
+                self.cache
+                    .lock()
+                    .expect("srv cache poisoned")
+                    .insert(key, (Instant::now() + records.ttl, records.targets.clone()));
+                records.targets
+            }
+        };
+        if targets.is_empty() {
+            return Err(resolve_error(name, "no SRV targets"));
+        }
+        Ok(order_targets(targets))
+    }
+
+    /// Returns the URLs for `name`, in the order they should be tried.
+    pub async fn resolve_urls(&self, name: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .resolve(name)
+            .await?
+            .iter()
+            .map(|t| format!("{}://{}:{}{}", self.scheme, t.target.trim_end_matches('.'), t.port, self.path))
+            .collect())
+    }
+}
+
+/// Orders targets by priority, then by a weighted random selection within each
+/// priority as described in RFC 2782.
+fn order_targets(mut targets: Vec<SrvTarget>) -> Vec<SrvTarget> {
+    // Zero weight targets go first, so they have a small chance of being picked.
+    targets.sort_by_key(|t| (t.priority, t.weight != 0));
+    let mut rng = rand::thread_rng();
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].priority;
+        let group_len = targets.iter().take_while(|t| t.priority == priority).count();
+        let mut group: Vec<SrvTarget> = targets.drain(..group_len).collect();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|t| u32::from(t.weight)).sum();
+            let pick = rng.gen_range(0..=total);
+            let mut running = 0;
+            let index = group
+                .iter()
+                .position(|t| {
+                    running += u32::from(t.weight);
+                    running >= pick
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+fn resolve_error(name: &str, reason: &str) -> Error {
+    Error::Resolve {
+        name: name.to_string(),
+        reason: reason.to_string(),
+    }
+}
@@ -23,3 +23,25 @@ pub const DEFAULT_MAPPING: &str = r#"
     "lang": "en"
 }
 "#;
+
+/// Language notifications default to, and the last in every language fallback
+/// chain.
+pub const DEFAULT_LANG: &str = "en";
+
+/// Name of the template set holding the default templates, registered in every
+/// TemplateEngine once per language below, ie "rqpush.fr.text".
+pub const DEFAULT_TEMPLATE_SET: &str = "rqpush";
+
+/// The footer of the default text and html templates.
+pub const GENERATED_BY: &str = "Email generated by";
+
+/// Translations of GENERATED_BY the default templates are localized in.
+pub const GENERATED_BY_TRANSLATIONS: [(&str, &str); 7] = [
+    ("en", GENERATED_BY),
+    ("de", "E-Mail erstellt von"),
+    ("es", "Correo generado por"),
+    ("fr", "E-mail généré par"),
+    ("it", "Email generata da"),
+    ("nl", "E-mail gegenereerd door"),
+    ("pt", "E-mail gerado por"),
+];
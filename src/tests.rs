@@ -1,5 +1,16 @@
-use crate::{Notification, generate_sha256, process_template};
+use crate::mock::{MockResponse, MockRqueue};
+use crate::receipt::interpret_response;
+use crate::{
+    ContentKey, Ed25519Keyring, Ed25519SigningKey, Encryption, Error, Fanout, FanoutPolicy,
+    FanoutReport, FlushReport, LookupFuture, LruNonceStore, Message, NonceStore, Notification,
+    OutboundNotification, RejectionKind, ReplayVerifier, RetryPolicy, Route, Router, RqueueClient,
+    SecretKeyring, SendReceipt, SignatureVersion, Signer, Spool, SpoolEntry, SrvLookup, SrvRecords,
+    SrvResolver, SrvTarget, StaticSrvTable, TemplateEngine, VerifyError, engine,
+    generate_hmac_sha256, generate_sha256, load_signing_key, load_verifying_key, process_template,
+    verify_hmac_sha256, verify_message_hmac,
+};
 use serde_json::Value;
+use std::time::{Duration, Instant};
 
 #[test]
 fn test_process_template() {
@@ -11,7 +22,7 @@ fn test_process_template() {
     });
 
     // Process template and confirm {{foo}} and {{notification}} are properly replaced.
-    let processed_template = process_template(notification, template, &mut values);
+    let processed_template = process_template("short_text", notification, template, &mut values).unwrap();
     assert_eq!(processed_template, "bar: This is an example.".to_string());
 }
 
@@ -31,7 +42,8 @@ fn test_notification() {
     assert_eq!(notification.title, "An example");
     assert_eq!(notification.short_text, "This is an example.");
     // Automatically set:
-    assert_eq!(notification.lang, "\"en\"");
+    assert_eq!(notification.lang, "en");
+    assert_eq!(notification.render().unwrap().lang, "en");
     // Values not automatically set:
     assert_eq!(notification.url, None);
     assert_eq!(notification.tagline, None);
@@ -55,4 +67,1170 @@ fn test_notification() {
     assert_eq!(notification.title, "An example");
     assert_eq!(notification.short_text, "This is an example.");
     assert_eq!(notification.tagline, None);
-}
\ No newline at end of file
+}
+#[test]
+fn test_compile() {
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let message = notification.compile("http://localhost:8000", 42, 60, &Signer::Legacy(Some("secret".to_string()))).unwrap();
+    // Priority and TTL are passed through to the message.
+    assert_eq!(message.priority, Some(42));
+    assert_eq!(message.ttl, Some(60));
+    // The sha256 is calculated from the contents, salted with the shared secret.
+    assert_eq!(message.sha256, Some(generate_sha256(&message.contents, Some("secret"))));
+    let contents: Value = serde_json::from_str(&message.contents).unwrap();
+    assert_eq!(contents["app"], "example");
+}
+
+#[test]
+fn test_render() {
+    let mut notification = Notification::init("example", "An example", "This is an example.");
+    notification.set_app("example");
+    notification.set_short_text_template("{{notification}} ({{adjective}})".to_string());
+    notification.set_priority(42);
+    notification.add_value("adjective".to_string(), "rendered".to_string());
+    let before = format!("{:?}", &notification);
+
+    // Rendering fills out defaults and templates without changing the notification.
+    let outbound = notification.render().unwrap();
+    assert_eq!(format!("{:?}", &notification), before);
+    assert_eq!(outbound.title, "[example] An example");
+    assert_eq!(outbound.tagline, "example");
+    assert_eq!(outbound.priority, 42);
+    assert_eq!(outbound.ttl, 0);
+    assert_eq!(outbound.short_text, "This is an example. (rendered)");
+    assert_eq!(notification.render().unwrap(), outbound);
+
+    // The built message contains exactly the rendered notification.
+    let message = notification.build_message(Some("secret")).unwrap();
+    assert_eq!(message.priority, Some(42));
+    assert_eq!(message.sha256, Some(generate_sha256(&message.contents, Some("secret"))));
+    let contents: OutboundNotification = serde_json::from_str(&message.contents).unwrap();
+    assert_eq!(contents, outbound);
+
+    // Rendering errors are reported without sending.
+    notification.set_title_template("{{#if}}".to_string());
+    assert!(matches!(notification.render(), Err(Error::TemplateCompile { field: "title", .. })));
+}
+
+#[test]
+fn test_repeated_send() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mock = MockRqueue::start().unwrap();
+    let mut notification = Notification::init("example", "An example", "This is an example.");
+    notification.set_tagline("An example app");
+    notification.set_title_template("[{{app}}] {{notification}} ({{category}})".to_string());
+    notification.set_short_text_template("{{title}}: {{notification}} -- {{tagline}}".to_string());
+    notification.set_category("examples");
+    let before = format!("{:?}", &notification);
+
+    // Sending the same notification twice delivers the same title and body.
+    runtime.block_on(notification.send_async(&mock.url(), 0, 0, None)).unwrap();
+    runtime.block_on(notification.send_async(&mock.url(), 0, 0, None)).unwrap();
+    assert_eq!(format!("{:?}", &notification), before);
+    assert_eq!(notification.title, "An example");
+    let messages = mock.messages();
+    assert_eq!(messages.len(), 2);
+    let first = messages[0].verify_and_open(None).unwrap();
+    let second = messages[1].verify_and_open(None).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first.title, "[example] An example (examples)");
+    assert_eq!(first.short_text, "[example] An example (examples): This is an example. -- An example app");
+
+    // Clones of a prototype render independently of each other and the prototype.
+    let client = RqueueClient::builder(&mock.url()).build().unwrap();
+    let mut clone = notification.clone();
+    clone.set_title("Another example");
+    runtime.block_on(client.send(&clone)).unwrap();
+    runtime.block_on(client.send(&notification)).unwrap();
+    let messages = mock.messages();
+    assert_eq!(messages[2].verify_and_open(None).unwrap().title, "[example] Another example (examples)");
+    assert_eq!(messages[3].verify_and_open(None).unwrap(), first);
+}
+
+#[test]
+fn test_client_priority_ttl() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mock = MockRqueue::start().unwrap();
+    let mut router = Router::new();
+    router.add_route(Route::new(&mock.url()).category("security_*").unwrap().priority(200).ttl(3600));
+    let client = RqueueClient::builder(&mock.url()).priority(10).ttl(30).router(router).build().unwrap();
+    assert_eq!(client.server(), mock.url());
+
+    // Client defaults apply to notifications without their own settings or a matching route.
+    let mut notification = Notification::init("example", "An example", "This is an example.");
+    assert_eq!(notification.priority, None);
+    runtime.block_on(client.send(&notification)).unwrap();
+
+    // Route settings take precedence over client defaults.
+    notification.set_category("security_login");
+    runtime.block_on(client.send(&notification)).unwrap();
+
+    // Notification settings take precedence over both.
+    notification.set_priority(99);
+    notification.set_ttl(5);
+    assert_eq!(notification.priority, Some(99));
+    assert_eq!(notification.ttl, Some(5));
+    runtime.block_on(client.send(&notification)).unwrap();
+
+    let sent: Vec<(Option<u8>, Option<u32>)> = mock.messages().iter().map(|m| (m.priority, m.ttl)).collect();
+    assert_eq!(sent, vec![(Some(10), Some(30)), (Some(200), Some(3600)), (Some(99), Some(5))]);
+}
+
+#[test]
+fn test_errors() {
+    // Required fields are validated.
+    match Notification::try_init("", "An example", "This is an example.") {
+        Err(Error::InvalidInput { field, .. }) => assert_eq!(field, "app"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    let notification = Notification::init("example", "", "This is an example.");
+    match notification.compile("http://localhost:8000", 0, 0, &Signer::default()) {
+        Err(Error::InvalidInput { field, .. }) => assert_eq!(field, "title"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Template errors are reported along with the field being rendered.
+    let mut values = json!({});
+    match process_template("title", "An example".to_string(), "{{#if}}".to_string(), &mut values) {
+        Err(Error::TemplateCompile { field, .. }) => assert_eq!(field, "title"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match process_template("long_html", "An example".to_string(), "{{> missing}}".to_string(), &mut values) {
+        Err(Error::TemplateRender { field, .. }) => assert_eq!(field, "long_html"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_interpret_response() {
+    let message = Message {
+        sha256: Some("abc".to_string()),
+        ..Default::default()
+    };
+
+    // 2xx responses produce a receipt, parsing any JSON body.
+    let receipt = interpret_response("http://localhost:8000", &message, 200, r#"{"id": 7}"#.to_string()).unwrap();
+    assert_eq!(receipt.status, 200);
+    assert_eq!(receipt.sha256, Some("abc".to_string()));
+    assert_eq!(receipt.body, Some(json!({"id": 7})));
+    let receipt = interpret_response("http://localhost:8000", &message, 204, "".to_string()).unwrap();
+    assert_eq!(receipt.body, None);
+
+    // Everything else is rejected and classified.
+    match interpret_response("http://localhost:8000", &message, 400, "invalid sha256".to_string()) {
+        Err(Error::Rejected { status, kind, body, .. }) => {
+            assert_eq!(status, 400);
+            assert_eq!(kind, RejectionKind::Auth);
+            assert_eq!(body, "invalid sha256");
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(RejectionKind::classify(403, ""), RejectionKind::Auth);
+    assert_eq!(RejectionKind::classify(410, "ttl expired"), RejectionKind::Validation);
+    assert_eq!(RejectionKind::classify(503, ""), RejectionKind::Server);
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy::default()
+        .max_attempts(4)
+        .base_delay(Duration::from_millis(100))
+        .max_delay(Duration::from_millis(250))
+        .jitter(0.0);
+    // Delays double with each attempt, up to max_delay.
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(2), Duration::from_millis(200));
+    assert_eq!(policy.delay(3), Duration::from_millis(250));
+    assert!(policy.allows_attempt(3));
+    assert!(!policy.allows_attempt(4));
+
+    // Jitter only ever shortens the delay.
+    let jittered = policy.clone().jitter(0.5).delay(2);
+    assert!(jittered >= Duration::from_millis(100) && jittered <= Duration::from_millis(200));
+
+    // Only configured statuses are retried.
+    let rejected = |status| Error::Rejected {
+        server: "http://localhost:8000".to_string(),
+        status,
+        kind: RejectionKind::classify(status, ""),
+        body: "".to_string(),
+    };
+    assert!(policy.is_retryable(&rejected(503)));
+    assert!(!policy.is_retryable(&rejected(500)));
+    assert!(!policy.is_retryable(&rejected(403)));
+    assert!(!RetryPolicy::never().allows_attempt(1));
+}
+
+#[test]
+fn test_retry_honors_ttl() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // Nothing listens on port 1, so every attempt fails to connect.
+    let policy = RetryPolicy::default().max_attempts(10).base_delay(Duration::from_secs(5)).jitter(0.0);
+    let client = RqueueClient::builder("http://127.0.0.1:1").retry_policy(policy).ttl(1).build().unwrap();
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let started = Instant::now();
+    match runtime.block_on(client.send(&notification)) {
+        Err(Error::Transport { .. }) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // The 5 second retry delay would outlive the 1 second TTL, so no retry happens.
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_spool() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("rqpush-test-spool-{}", std::process::id()));
+    let spool = Spool::open(&dir).unwrap();
+
+    // Nothing listens on port 1, so the notification is spooled.
+    let client = RqueueClient::builder("http://127.0.0.1:1").spool(spool.clone()).ttl(60).build().unwrap();
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let path = match runtime.block_on(client.send(&notification)) {
+        Err(Error::Spooled { path, source }) => {
+            assert!(matches!(*source, Error::Transport { .. }));
+            path
+        }
+        r => panic!("unexpected result: {:?}", r),
+    };
+    let entries = spool.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0, path);
+    assert_eq!(entries[0].1.server, "http://127.0.0.1:1");
+    assert_eq!(entries[0].1.message.ttl, Some(60));
+
+    // The server is still down, so the entry stays in the spool.
+    let report = runtime.block_on(client.flush_spool()).unwrap();
+    assert_eq!(report.remaining, 1);
+
+    // Entries are dropped once their TTL elapses.
+    let entry = &entries[0].1;
+    assert_eq!(entry.remaining_ttl(entry.created + 20), Some(40));
+    assert!(!entry.is_expired(entry.created + 59));
+    assert!(entry.is_expired(entry.created + 60));
+    let expired = Message {
+        ttl: Some(1),
+        ..entry.message.clone()
+    };
+    let mut file: SpoolEntry = entry.clone();
+    file.message = expired;
+    file.created -= 10;
+    std::fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+    let report = runtime.block_on(client.flush_spool()).unwrap();
+    assert_eq!(report.expired, 1);
+    assert!(spool.entries().unwrap().is_empty());
+
+    // TTLs count from when the message was first sent, not when it was spooled.
+    let signed = notification.compile("http://127.0.0.1:1", 0, 60, &Signer::HmacSha256("secret".to_string())).unwrap();
+    let path = spool.push("http://127.0.0.1:1", &signed).unwrap();
+    assert_eq!(spool.entries().unwrap()[0].1.created, signed.timestamp.unwrap());
+    spool.remove(&path).unwrap();
+    spool.push_at("http://127.0.0.1:1", &entry.message, 1_000).unwrap();
+    assert_eq!(spool.entries().unwrap()[0].1.created, 1_000);
+    let report = runtime.block_on(client.flush_spool()).unwrap();
+    assert_eq!(report.expired, 1);
+
+    // Removing an entry that's already gone isn't an error.
+    spool.remove(&path).unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_spool_concurrent_flush() {
+    let dir = std::env::temp_dir().join(format!("rqpush-test-spool-flush-{}", std::process::id()));
+    let spool = Spool::open(&dir).unwrap();
+    let mock = MockRqueue::start().unwrap();
+    mock.set_fallback(MockResponse::default().delay(Duration::from_millis(20)));
+    let notification = Notification::init("example", "An example", "This is an example.");
+    for _ in 0..10 {
+        spool.push(&mock.url(), &notification.build_message(None).unwrap()).unwrap();
+    }
+
+    // Each entry is claimed by one flush, so none is delivered twice.
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = RqueueClient::builder(&mock.url()).spool(spool.clone()).build().unwrap();
+    let flushes: Vec<_> = (0..2)
+        .map(|_| {
+            let client = client.clone();
+            runtime.spawn(async move { client.flush_spool().await })
+        })
+        .collect();
+    let reports: Vec<FlushReport> = flushes.into_iter().map(|flush| runtime.block_on(flush).unwrap().unwrap()).collect();
+    assert_eq!(reports.iter().map(|r| r.delivered).sum::<usize>(), 10);
+    assert_eq!(mock.requests(), 10);
+    assert!(spool.entries().unwrap().is_empty());
+
+    // Entries left claimed by a flush that never finished can be released.
+    let path = spool.push(&mock.url(), &notification.build_message(None).unwrap()).unwrap();
+    let claimed = spool.claim(&path).unwrap().unwrap();
+    assert_eq!(spool.claim(&path).unwrap(), None);
+    assert!(spool.entries().unwrap().is_empty());
+    assert_eq!(spool.release_all().unwrap(), 1);
+    assert!(!claimed.exists());
+    assert_eq!(runtime.block_on(client.flush_spool()).unwrap().delivered, 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_srv_resolver() {
+    let table = StaticSrvTable::parse(
+        "
+        # Primary servers, rq2 gets most of the traffic.
+        _rqueue._tcp.example.com 10 0 8000 rq1.example.com.
+        _rqueue._tcp.example.com 10 100 8000 rq2.example.com.
+        _rqueue._tcp.example.com 20 50 9000 backup.example.com.
+        ",
+    )
+    .unwrap();
+    assert!(StaticSrvTable::parse("_rqueue._tcp.example.com 10 0 8000").is_err());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let resolver = SrvResolver::new(table).scheme("https");
+    let urls = runtime.block_on(resolver.resolve_urls("_rqueue._tcp.example.com.")).unwrap();
+    assert_eq!(urls.len(), 3);
+    // Lower priority values are always tried first.
+    assert!(urls[..2].contains(&"https://rq1.example.com:8000/".to_string()));
+    assert!(urls[..2].contains(&"https://rq2.example.com:8000/".to_string()));
+    assert_eq!(urls[2], "https://backup.example.com:9000/");
+
+    match runtime.block_on(resolver.resolve("_rqueue._tcp.example.org")) {
+        Err(Error::Resolve { name, .. }) => assert_eq!(name, "_rqueue._tcp.example.org"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_srv_failover() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // Nothing listens on ports 1 or 2, so both targets are tried.
+    let mut table = StaticSrvTable::new();
+    table.insert("_rqueue._tcp.example.com", SrvTarget { priority: 1, weight: 0, port: 1, target: "127.0.0.1".to_string() });
+    table.insert("_rqueue._tcp.example.com", SrvTarget { priority: 2, weight: 0, port: 2, target: "127.0.0.1".to_string() });
+    let client = RqueueClient::builder("_rqueue._tcp.example.com")
+        .srv_resolver(SrvResolver::new(table))
+        .build()
+        .unwrap();
+    let notification = Notification::init("example", "An example", "This is an example.");
+    match runtime.block_on(client.send(&notification)) {
+        Err(Error::Transport { server, .. }) => assert_eq!(server, "http://127.0.0.1:2/"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[derive(Debug, Default)]
+struct CountingLookup {
+    lookups: std::sync::atomic::AtomicUsize,
+    ttl: Duration,
+}
+
+impl SrvLookup for CountingLookup {
+    fn lookup_srv<'a>(&'a self, _name: &'a str) -> LookupFuture<'a> {
+        self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let targets = vec![SrvTarget { priority: 1, weight: 1, port: 8000, target: "rq.example.com".to_string() }];
+        let ttl = self.ttl;
+        Box::pin(async move { Ok(SrvRecords { targets, ttl }) })
+    }
+}
+
+#[test]
+fn test_srv_cache() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let lookup = std::sync::Arc::new(CountingLookup { ttl: Duration::from_secs(300), ..Default::default() });
+    let resolver = SrvResolver::new(lookup.clone());
+    for _ in 0..3 {
+        runtime.block_on(resolver.resolve("_rqueue._tcp.example.com")).unwrap();
+    }
+    // Results are cached for the DNS TTL.
+    assert_eq!(lookup.lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    let lookup = std::sync::Arc::new(CountingLookup::default());
+    let resolver = SrvResolver::new(lookup.clone());
+    for _ in 0..3 {
+        runtime.block_on(resolver.resolve("_rqueue._tcp.example.com")).unwrap();
+    }
+    // A zero TTL is never cached.
+    assert_eq!(lookup.lookups.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+
+#[test]
+fn test_router() {
+    let router = Router::from_toml(
+        r#"
+        [[route]]
+        category = "security_*"
+        server = "http://127.0.0.1:1/"
+        priority = 200
+
+        [[route]]
+        app = "re:^(netgrasp|nmap)$"
+        lang = "en"
+        server = "http://network.example.com:8000"
+        ttl = 3600
+
+        [fallback]
+        server = "http://rqueue.example.com:8000"
+        "#,
+    )
+    .unwrap();
+    assert!(Router::from_toml("[[route]]\ncategory = \"[\"\nserver = \"x\"").is_err());
+
+    let mut notification = Notification::init("netgrasp", "An example", "This is an example.");
+    assert_eq!(router.route(&notification).unwrap().server, "http://network.example.com:8000");
+    notification.set_lang("de");
+    assert_eq!(router.route(&notification).unwrap().server, "http://rqueue.example.com:8000");
+    notification.set_category("security_alert");
+    let route = router.route(&notification).unwrap();
+    assert_eq!(route.server, "http://127.0.0.1:1/");
+    assert_eq!(route.priority, Some(200));
+
+    // The client sends to the server picked by the router.
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = RqueueClient::builder("http://rqueue.example.com:8000").router(router).build().unwrap();
+    match runtime.block_on(client.send(&notification)) {
+        Err(Error::Transport { server, .. }) => assert_eq!(server, "http://127.0.0.1:1/"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Without a fallback, unmatched notifications have no route.
+    let mut router = Router::new();
+    router.add_route(Route::new("http://security.example.com").category("security_*").unwrap());
+    assert!(router.route(&Notification::init("example", "An example", "This is an example.")).is_none());
+}
+
+#[test]
+fn test_fanout() {
+    let receipt = SendReceipt { server: "a".to_string(), status: 200, sha256: None, body: None };
+    let refused = |server: &str| Error::Rejected {
+        server: server.to_string(),
+        status: 503,
+        kind: RejectionKind::Server,
+        body: "".to_string(),
+    };
+    let report = |policy| FanoutReport {
+        policy,
+        outcomes: vec![("a".to_string(), Ok(receipt.clone())), ("b".to_string(), Err(refused("b"))), ("c".to_string(), Err(refused("c")))],
+    };
+    assert_eq!(report(FanoutPolicy::All).successes(), 1);
+    assert!(!report(FanoutPolicy::All).is_success());
+    assert!(report(FanoutPolicy::Any).is_success());
+    assert!(report(FanoutPolicy::Quorum(1)).is_success());
+    assert!(!report(FanoutPolicy::Quorum(2)).is_success());
+
+    // Nothing listens on ports 1 or 2, so every destination fails.
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = RqueueClient::builder("http://127.0.0.1:1").build().unwrap();
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let fanout = Fanout::new(&["http://127.0.0.1:1", "http://127.0.0.1:2"]).policy(FanoutPolicy::Any).parallel(true);
+    match runtime.block_on(client.send_fanout(&notification, &fanout)) {
+        Err(Error::Fanout(report)) => {
+            assert_eq!(report.outcomes.len(), 2);
+            assert_eq!(report.outcomes[1].0, "http://127.0.0.1:2");
+            assert!(matches!(report.outcomes[1].1, Err(Error::Transport { .. })));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_send_batch() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let notifications = vec![
+        Notification::init("example", "", "This is an invalid example."),
+        Notification::init("example", "An example", "This is an example."),
+    ];
+    // Nothing listens on port 1; the bulk request fails, so messages are sent individually.
+    for builder in [RqueueClient::builder("http://127.0.0.1:1"), RqueueClient::builder("http://127.0.0.1:1").bulk_path("/bulk")] {
+        let client = builder.batch_concurrency(2).build().unwrap();
+        let results = runtime.block_on(client.send_batch(&notifications));
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0], Err(Error::InvalidInput { field: "title", .. })));
+        match &results[1] {
+            Err(Error::Transport { server, .. }) => assert_eq!(server, "http://127.0.0.1:1"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn test_hmac_sha256() {
+    // RFC 4231 test case 2.
+    let hmac = generate_hmac_sha256("what do ya want for nothing?", "Jefe");
+    assert_eq!(hmac, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    assert!(verify_hmac_sha256("what do ya want for nothing?", "Jefe", &hmac));
+    assert!(!verify_hmac_sha256("what do ya want for nothing?", "jefe", &hmac));
+    assert!(!verify_hmac_sha256("what do ya want for nothing?", "Jefe", "not hex"));
+
+    // HMAC signed messages carry a versioned signature, and an unsalted sha256.
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let message = notification.compile("http://localhost:8000", 0, 0, &Signer::HmacSha256("secret".to_string())).unwrap();
+    assert_eq!(message.signature_version, Some(SignatureVersion::HmacSha256V2));
+    assert_eq!(verify_message_hmac(&message, "secret"), Ok(()));
+    assert_eq!(verify_message_hmac(&message, "wrong"), Err(VerifyError::SignatureMismatch));
+    assert_eq!(message.sha256, Some(generate_sha256(&message.contents, None)));
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["signature_version"], "hmac-sha256-v2");
+
+    // Version 1 signatures only cover the contents.
+    let v1 = Message {
+        signature_version: Some(SignatureVersion::HmacSha256V1),
+        signature: Some(generate_hmac_sha256(&message.contents, "secret")),
+        ..message.clone()
+    };
+    assert_eq!(verify_message_hmac(&v1, "secret"), Ok(()));
+
+    // Legacy messages don't serialize the new fields, so old servers are unaffected.
+    let message = notification.compile("http://localhost:8000", 0, 0, &Signer::Legacy(Some("secret".to_string()))).unwrap();
+    let json = serde_json::to_value(&message).unwrap();
+    assert!(json.get("signature").is_none());
+    assert!(json.get("signature_version").is_none());
+    assert!(json.get("timestamp").is_none());
+
+    // Secrets never show up in debug output.
+    let client = RqueueClient::builder("http://localhost:8000").signer(Signer::HmacSha256("supersecret".to_string())).build().unwrap();
+    assert!(!format!("{:?}", client).contains("supersecret"));
+    let builder = RqueueClient::builder("http://localhost:8000").shared_secret("supersecret");
+    assert!(!format!("{:?}", builder).contains("supersecret"));
+    assert_eq!(format!("{:?}", Signer::HmacSha256("supersecret".to_string())), "HmacSha256(..)");
+    assert_eq!(format!("{:?}", Signer::Legacy(Some("supersecret".to_string()))), "Legacy(Some(..))");
+}
+
+#[test]
+fn test_replay_verifier() {
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let signer = Signer::HmacSha256("secret".to_string());
+    let message = notification.compile("http://localhost:8000", 0, 0, &signer).unwrap();
+    let timestamp = message.timestamp.unwrap();
+    assert_eq!(message.nonce.as_ref().unwrap().len(), 32);
+
+    let verifier = ReplayVerifier::new("secret").max_skew(Duration::from_secs(60));
+    assert_eq!(verifier.verify(&message), Ok(()));
+    // The same nonce can't be used twice.
+    assert_eq!(verifier.verify(&message), Err(VerifyError::Replayed(message.nonce.clone().unwrap())));
+
+    // Messages outside the clock skew window are rejected.
+    let message = notification.compile("http://localhost:8000", 0, 0, &signer).unwrap();
+    assert_eq!(
+        verifier.verify_at(&message, timestamp + 61),
+        Err(VerifyError::ClockSkew { timestamp, now: timestamp + 61 })
+    );
+
+    // The timestamp and nonce are covered by the signature.
+    let tampered = Message {
+        timestamp: Some(timestamp + 1),
+        ..message.clone()
+    };
+    assert_eq!(verifier.verify(&tampered), Err(VerifyError::SignatureMismatch));
+    let tampered = Message {
+        nonce: Some("0".repeat(32)),
+        ..message.clone()
+    };
+    assert_eq!(verifier.verify(&tampered), Err(VerifyError::SignatureMismatch));
+
+    // Legacy messages offer no replay protection.
+    let legacy = notification.compile("http://localhost:8000", 0, 0, &Signer::Legacy(Some("secret".to_string()))).unwrap();
+    assert_eq!(verifier.verify(&legacy), Err(VerifyError::MissingSignature));
+
+    // The LRU store forgets the oldest nonces once full.
+    let store = LruNonceStore::new(2);
+    assert!(store.insert("a", 0));
+    assert!(store.insert("b", 0));
+    assert!(!store.insert("a", 0));
+    assert!(store.insert("c", 0));
+    assert!(store.insert("a", 0));
+}
+
+#[test]
+fn test_ed25519() {
+    let key = Ed25519SigningKey::from_bytes(&[7u8; 32]);
+    let signer = Signer::ed25519("example-1", key.clone());
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let message = notification.compile("http://localhost:8000", 0, 0, &signer).unwrap();
+    assert_eq!(message.signature_version, Some(SignatureVersion::Ed25519V1));
+    assert_eq!(message.key_id, Some("example-1".to_string()));
+    assert_eq!(message.sha256, Some(generate_sha256(&message.contents, None)));
+    assert_eq!(verify_message_hmac(&message, "secret"), Err(VerifyError::UnsupportedSignature(Some(SignatureVersion::Ed25519V1))));
+    assert_eq!(format!("{:?}", signer), r#"Ed25519 { key_id: "example-1", .. }"#);
+
+    // Keys load from PEM files, raw files and hex encoded files.
+    let dir = std::env::temp_dir().join(format!("rqpush-test-ed25519-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey, EncodePublicKey};
+    std::fs::write(dir.join("private.pem"), key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    std::fs::write(dir.join("private.key"), key.to_bytes()).unwrap();
+    std::fs::write(dir.join("public.pem"), key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+    std::fs::write(dir.join("public.hex"), format!("{}\n", hex::encode(key.verifying_key().to_bytes()))).unwrap();
+    std::fs::write(dir.join("bad.key"), "not a key").unwrap();
+    assert_eq!(load_signing_key(dir.join("private.pem")).unwrap(), key);
+    assert_eq!(load_signing_key(dir.join("private.key")).unwrap(), key);
+    assert_eq!(load_verifying_key(dir.join("public.pem")).unwrap(), key.verifying_key());
+    assert_eq!(load_verifying_key(dir.join("public.hex")).unwrap(), key.verifying_key());
+    assert!(matches!(load_signing_key(dir.join("bad.key")), Err(Error::InvalidInput { field: "key", .. })));
+    assert!(matches!(load_verifying_key(dir.join("missing.pem")), Err(Error::Io { .. })));
+
+    // The keyring verifies with public keys only, and checks the app matches the key.
+    let mut keyring = Ed25519Keyring::new();
+    keyring.insert_file("example-1", "example", dir.join("public.pem")).unwrap();
+    let outbound = keyring.verify(&message).unwrap();
+    assert_eq!(outbound.app, "example");
+    let other = Notification::init("other", "An example", "This is an example.");
+    let forged = other.compile("http://localhost:8000", 0, 0, &signer).unwrap();
+    assert_eq!(
+        keyring.verify(&forged).unwrap_err(),
+        VerifyError::AppMismatch {
+            key_id: "example-1".to_string(),
+            expected: "example".to_string(),
+            actual: "other".to_string(),
+        }
+    );
+    let tampered = Message {
+        contents: message.contents.replace("An example", "Another example"),
+        ..message.clone()
+    };
+    assert_eq!(keyring.verify(&tampered).unwrap_err(), VerifyError::SignatureMismatch);
+    let unknown = Message {
+        key_id: Some("example-2".to_string()),
+        ..message.clone()
+    };
+    assert_eq!(keyring.verify(&unknown).unwrap_err(), VerifyError::UnknownKey("example-2".to_string()));
+    let wrong_key = Signer::ed25519("example-1", Ed25519SigningKey::from_bytes(&[8u8; 32]));
+    let message = notification.compile("http://localhost:8000", 0, 0, &wrong_key).unwrap();
+    assert_eq!(keyring.verify(&message).unwrap_err(), VerifyError::SignatureMismatch);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_secret_keyring() {
+    // Messages are signed with the current key, and carry its id.
+    let old = SecretKeyring::new("2023", "old secret");
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let message = notification.compile("http://localhost:8000", 0, 0, &Signer::Keyring(old.clone())).unwrap();
+    assert_eq!(message.key_id, Some("2023".to_string()));
+    assert_eq!(message.signature_version, Some(SignatureVersion::HmacSha256V2));
+    assert_eq!(verify_message_hmac(&message, "old secret"), Ok(()));
+
+    // Receivers accept the new key before producers switch to it.
+    let mut receiver = old.clone().accept("2024", "new secret");
+    assert_eq!(receiver.current_key_id(), Some("2023"));
+    let rotated = old.current("2024", "new secret");
+    assert_eq!(rotated.current_key_id(), Some("2024"));
+    let new_message = notification.compile("http://localhost:8000", 0, 0, &Signer::Keyring(rotated)).unwrap();
+    assert_eq!(new_message.key_id, Some("2024".to_string()));
+    assert_eq!(receiver.verify(&message), Ok(()));
+    assert_eq!(receiver.verify(&new_message), Ok(()));
+
+    // Once the old key is dropped, its messages are rejected.
+    receiver = receiver.current("2024", "new secret");
+    assert!(receiver.remove("2023"));
+    assert!(!receiver.remove("2024"));
+    assert_eq!(receiver.verify(&message), Err(VerifyError::UnknownKey("2023".to_string())));
+    let swapped = Message {
+        key_id: Some("2024".to_string()),
+        ..message.clone()
+    };
+    assert_eq!(receiver.verify(&swapped), Err(VerifyError::SignatureMismatch));
+
+    // Messages without a key id are only accepted with an unnamed secret.
+    let unnamed = notification.compile("http://localhost:8000", 0, 0, &Signer::HmacSha256("legacy".to_string())).unwrap();
+    assert_eq!(unnamed.key_id, None);
+    assert_eq!(receiver.verify(&unnamed), Err(VerifyError::MissingKeyId));
+    let receiver = receiver.accept_unnamed("legacy");
+    assert_eq!(receiver.verify(&unnamed), Ok(()));
+
+    // A keyring without a key to sign with is rejected, rather than signing
+    // with an empty secret.
+    let empty = Signer::Keyring(SecretKeyring::default().accept("2024", "new secret"));
+    assert!(matches!(notification.compile("http://localhost:8000", 0, 0, &empty), Err(Error::InvalidInput { field: "signer", .. })));
+    assert!(matches!(
+        RqueueClient::builder("http://localhost:8000").signer(empty.clone()).build(),
+        Err(Error::InvalidInput { field: "signer", .. })
+    ));
+    let mut unsigned = message.clone();
+    empty.sign(&mut unsigned);
+    assert_eq!(unsigned.signature, None);
+    assert_eq!(verify_message_hmac(&unsigned, ""), Err(VerifyError::MissingSignature));
+
+    // Debug output lists key ids, never secrets.
+    let debug = format!("{:?}", Signer::Keyring(receiver.clone().accept("2025", "next secret")));
+    assert!(debug.contains(r#"keys: ["2024", "2025"]"#));
+    assert!(!debug.contains("secret\""));
+
+    // Replay verification honors the keyring.
+    let verifier = ReplayVerifier::new(receiver);
+    assert_eq!(verifier.verify(&new_message), Ok(()));
+    assert_eq!(verifier.verify(&unnamed), Ok(()));
+    assert_eq!(verifier.verify(&message), Err(VerifyError::UnknownKey("2023".to_string())));
+}
+
+#[test]
+fn test_content_encryption() {
+    let key = ContentKey::derive("secret", "example");
+    let signer = Signer::HmacSha256("secret".to_string());
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let message = notification.compile_sealed("http://localhost:8000", 42, 60, Some(&key), &signer).unwrap();
+    assert_eq!(message.encryption, Some(Encryption::XChaCha20Poly1305V1));
+    assert!(!message.contents.contains("An example"));
+
+    // Priority and TTL stay visible, and the signature covers the ciphertext.
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["priority"], 42);
+    assert_eq!(json["ttl"], 60);
+    assert_eq!(json["encryption"], "xchacha20poly1305-v1");
+    assert_eq!(verify_message_hmac(&message, "secret"), Ok(()));
+    assert_eq!(message.sha256, Some(generate_sha256(&message.contents, None)));
+
+    // Consumers holding the key can read the notification.
+    let outbound = key.open(&message).unwrap();
+    assert_eq!(outbound.title, "[example] An example");
+    assert!(outbound.short_text.starts_with("This is an example."));
+    assert_eq!(ContentKey::derive("secret", "other").decrypt(&message), Err(VerifyError::DecryptionFailed));
+    assert_eq!(ContentKey::new([0u8; 32]).decrypt(&message), Err(VerifyError::DecryptionFailed));
+    let mut tampered = message.clone();
+    tampered.contents.replace_range(40..44, "AAAA");
+    assert_eq!(key.decrypt(&tampered), Err(VerifyError::DecryptionFailed));
+    let plaintext = notification.compile("http://localhost:8000", 42, 0, &signer).unwrap();
+    assert_eq!(plaintext.encryption, None);
+    assert_eq!(key.decrypt(&plaintext), Err(VerifyError::NotEncrypted));
+
+    // Encrypted messages are sealed with a fresh nonce every time.
+    let again = notification.compile_sealed("http://localhost:8000", 42, 60, Some(&key), &signer).unwrap();
+    assert_ne!(again.contents, message.contents);
+
+    // The Ed25519 keyring decrypts before checking the app.
+    let signing_key = Ed25519SigningKey::from_bytes(&[7u8; 32]);
+    let signer = Signer::ed25519("example-1", signing_key.clone());
+    let message = notification.compile_sealed("http://localhost:8000", 42, 60, Some(&key), &signer).unwrap();
+    let mut keyring = Ed25519Keyring::new();
+    keyring.insert("example-1", "example", signing_key.verifying_key());
+    assert_eq!(keyring.verify(&message).unwrap_err(), VerifyError::Encrypted);
+    let keyring = keyring.content_key(key);
+    assert_eq!(keyring.verify(&message).unwrap().app, "example");
+}
+
+#[test]
+fn test_verify_and_open() {
+    let notification = Notification::init("example", "An example", "This is an example.");
+
+    // Legacy messages are checked against their salted sha256.
+    let message = notification.compile("http://localhost:8000", 0, 0, &Signer::Legacy(Some("secret".to_string()))).unwrap();
+    let outbound = message.verify_and_open(Some("secret")).unwrap();
+    assert_eq!(outbound.app, "example");
+    assert_eq!(message.verify_and_open(Some("wrong")).unwrap_err(), VerifyError::HashMismatch);
+    assert_eq!(message.verify_and_open(None).unwrap_err(), VerifyError::HashMismatch);
+    let unsalted = notification.compile("http://localhost:8000", 0, 0, &Signer::Legacy(None)).unwrap();
+    assert_eq!(unsalted.verify(None), Ok(()));
+    let missing = Message {
+        sha256: None,
+        ..message.clone()
+    };
+    assert_eq!(missing.verify(Some("secret")), Err(VerifyError::MissingHash));
+
+    // Tampered contents no longer match the hash, and unparseable contents are reported.
+    let tampered = Message {
+        contents: message.contents.replace("An example", "Another example"),
+        ..message.clone()
+    };
+    assert_eq!(tampered.verify(Some("secret")), Err(VerifyError::HashMismatch));
+    let malformed = Message {
+        sha256: Some(generate_sha256("not json", Some("secret"))),
+        contents: "not json".to_string(),
+        ..message.clone()
+    };
+    assert!(matches!(malformed.verify_and_open(Some("secret")), Err(VerifyError::MalformedContents(_))));
+
+    // HMAC signed messages are checked against their signature too.
+    let signed = notification.compile("http://localhost:8000", 0, 0, &Signer::HmacSha256("secret".to_string())).unwrap();
+    assert_eq!(signed.verify_and_open(Some("secret")).unwrap().app, "example");
+    assert_eq!(signed.verify(Some("wrong")), Err(VerifyError::SignatureMismatch));
+    assert_eq!(signed.verify(None), Err(VerifyError::UnsupportedSignature(Some(SignatureVersion::HmacSha256V2))));
+
+    // Encrypted messages verify, but must be opened with their key.
+    let key = ContentKey::derive("secret", "example");
+    let sealed = notification.compile_sealed("http://localhost:8000", 0, 0, Some(&key), &Signer::Legacy(Some("secret".to_string()))).unwrap();
+    assert_eq!(sealed.verify(Some("secret")), Ok(()));
+    assert_eq!(sealed.verify_and_open(Some("secret")), Err(VerifyError::Encrypted));
+    assert_eq!(key.open(&sealed).unwrap().app, "example");
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn test_mock_send() {
+    let mock = MockRqueue::start_with_secret(Some("secret")).unwrap();
+
+    // Notification::send delivers a salted legacy message.
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let receipt = notification.send(&mock.url(), 42, 60, Some("secret")).unwrap();
+    assert_eq!(receipt.status, 200);
+    assert_eq!(receipt.body, Some(json!({"status": "queued"})));
+    let messages = mock.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].priority, Some(42));
+    assert_eq!(messages[0].ttl, Some(60));
+    assert_eq!(receipt.sha256, messages[0].sha256);
+    assert_eq!(messages[0].verify_and_open(Some("secret")).unwrap().app, "example");
+
+    // A wrong secret is rejected as an authentication failure.
+    match notification.send(&mock.url(), 42, 60, Some("wrong")) {
+        Err(Error::Rejected { status: 401, kind: RejectionKind::Auth, .. }) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(mock.received()[1].verified, Err(VerifyError::HashMismatch));
+    assert_eq!(mock.messages().len(), 1);
+
+    // The async send and HMAC signed messages are verified too.
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(notification.send_async(&mock.url(), 1, 0, Some("secret"))).unwrap();
+    let client = RqueueClient::builder(&mock.url()).signer(Signer::HmacSha256("secret".to_string())).build_blocking().unwrap();
+    client.send(&notification).unwrap();
+    let messages = mock.messages();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[2].signature_version, Some(SignatureVersion::HmacSha256V2));
+
+    // Batches are posted to the bulk path.
+    let client = RqueueClient::builder(&mock.url()).shared_secret("secret").bulk_path("/bulk").build_blocking().unwrap();
+    let results = client.send_batch(&[notification.clone(), notification.clone()]);
+    assert!(results.iter().all(|r| r.is_ok()));
+    let received = mock.received();
+    assert_eq!(received.len(), 6);
+    assert!(received[4..].iter().all(|r| r.path == "/bulk" && r.verified.is_ok()));
+}
+
+#[test]
+fn test_mock_responses() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mock = MockRqueue::start().unwrap();
+    let notification = Notification::init("example", "An example", "This is an example.");
+    let policy = RetryPolicy::default().base_delay(Duration::from_millis(1)).retry_statuses(&[503]);
+    let client = RqueueClient::builder(&mock.url()).retry_policy(policy).build().unwrap();
+
+    // Retryable statuses are retried until RQueue accepts the notification.
+    mock.respond(&[MockResponse::status(503), MockResponse::status(503)]);
+    assert_eq!(runtime.block_on(client.send(&notification)).unwrap().status, 200);
+    assert_eq!(mock.requests(), 3);
+
+    // Other statuses are rejected immediately.
+    mock.respond(&[MockResponse::status(400).body(r#"{"error": "invalid ttl"}"#)]);
+    match runtime.block_on(client.send(&notification)) {
+        Err(Error::Rejected { status: 400, kind: RejectionKind::Validation, body, .. }) => assert!(body.contains("invalid ttl")),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(mock.requests(), 4);
+
+    // Dropped connections and slow responses are transport failures.
+    mock.respond(&[MockResponse::disconnect()]);
+    assert!(matches!(runtime.block_on(client.send(&notification)), Err(Error::Transport { .. })));
+    let client = RqueueClient::builder(&mock.url()).timeout(Duration::from_millis(50)).build().unwrap();
+    mock.set_fallback(MockResponse::default().delay(Duration::from_millis(500)));
+    match runtime.block_on(client.send(&notification)) {
+        Err(Error::Transport { source, .. }) => assert!(source.is_timeout()),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_mock_bulk() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mock = MockRqueue::start().unwrap();
+    let notifications = vec![Notification::init("example", "An example", "This is an example."); 2];
+    let client = RqueueClient::builder(&mock.url()).bulk_path("/bulk").timeout(Duration::from_millis(100)).build().unwrap();
+
+    // RQueue may have processed a bulk request that timed out, so it isn't resent.
+    mock.respond(&[MockResponse::default().delay(Duration::from_millis(300))]);
+    let results = runtime.block_on(client.send_batch(&notifications));
+    for result in &results {
+        match result {
+            Err(Error::Bulk(source)) => assert!(matches!(source.as_ref(), Error::Transport { source, .. } if source.is_timeout())),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+    assert_eq!(mock.received().len(), 2);
+
+    // Other failures are returned for each notification.
+    mock.respond(&[MockResponse::status(503)]);
+    let results = runtime.block_on(client.send_batch(&notifications));
+    assert!(results.iter().all(|r| matches!(r, Err(Error::Rejected { status: 503, kind: RejectionKind::Server, .. }))));
+    assert_eq!(mock.received().len(), 4);
+
+    // Servers without bulk support are sent each notification individually.
+    for status in [404, 405, 501] {
+        let start = mock.received().len();
+        mock.respond(&[MockResponse::status(status)]);
+        let results = runtime.block_on(client.send_batch(&notifications));
+        assert!(results.iter().all(|r| r.is_ok()));
+        let received = mock.received();
+        assert_eq!(received.len(), start + 4);
+        assert!(received[start..start + 2].iter().all(|r| r.path == "/bulk"));
+        assert!(received[start + 2..].iter().all(|r| r.path == "/"));
+    }
+}
+
+#[test]
+fn test_template_engine() {
+    // Inline templates are compiled once, and the least recently used are dropped.
+    let engine = TemplateEngine::new(2);
+    let values = json!({"notification": "An example", "app": "example"});
+    assert_eq!(engine.render_inline("title", "[{{app}}] {{notification}}", &values).unwrap(), "[example] An example");
+    assert_eq!(engine.render_inline("title", "[{{app}}] {{notification}}", &values).unwrap(), "[example] An example");
+    assert_eq!(engine.cached_templates(), 1);
+    engine.render_inline("short_text", "{{notification}}", &values).unwrap();
+    engine.render_inline("title", "[{{app}}] {{notification}}", &values).unwrap();
+    engine.render_inline("long_text", "{{app}}", &values).unwrap();
+    assert_eq!(engine.cached_templates(), 2);
+    assert!(engine.has_template(&format!("sha256:{}", generate_sha256("[{{app}}] {{notification}}", None))));
+    assert!(!engine.has_template(&format!("sha256:{}", generate_sha256("{{notification}}", None))));
+    assert!(matches!(engine.render_inline("title", "{{#if}}", &values), Err(Error::TemplateCompile { field: "title", .. })));
+    assert_eq!(engine.cached_templates(), 2);
+
+    // Named templates are registered explicitly and aren't evicted.
+    engine.register_template("greeting", "Hello {{app}}").unwrap();
+    assert_eq!(engine.render("greeting", &values).unwrap(), "Hello example");
+    assert!(matches!(engine.register_template("broken", "{{#if}}"), Err(Error::TemplateCompile { .. })));
+    assert!(matches!(engine.render("missing", &values), Err(Error::TemplateRender { .. })));
+
+    // Sets are tracked as their templates are registered, with or without a
+    // language, while inline templates belong to no set.
+    assert!(!engine.has_set("greeting"));
+    engine.register_template("welcome.pt-BR.text", "Olá {{app}}").unwrap();
+    engine.register_template("welcome.title", "Welcome to {{app}}").unwrap();
+    engine.register_template("welcome.title", "Welcome to {{app}}!").unwrap();
+    assert!(engine.has_set("welcome"));
+    assert!(engine.has_set("welcome.pt-BR"));
+    assert!(!engine.has_set("welcome.pt"));
+    assert!(!engine.has_set("sha256"));
+
+    // The engine is shared across threads.
+    let engine = std::sync::Arc::new(engine);
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                let values = json!({"notification": i});
+                engine.render_inline("title", &format!("{}: {{{{notification}}}}", i % 3), &values).unwrap()
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap(), format!("{}: {}", i % 3, i));
+    }
+    assert_eq!(engine.render("greeting", &values).unwrap(), "Hello example");
+}
+
+#[test]
+fn test_template_helpers() {
+    let engine = TemplateEngine::default();
+    let values = json!({
+        "when": 1_700_000_000,
+        "since": "2023-11-14T23:13:20+01:00",
+        "devices": ["laptop", "phone", "tablet", "watch"],
+        "one": ["laptop"],
+        "name": "Lorem ipsum dolor",
+        "empty": "",
+    });
+    let render = |source: &str| engine.render_inline("title", source, &values);
+
+    assert_eq!(render("{{date when}}").unwrap(), "2023-11-14 22:13:20 UTC");
+    assert_eq!(render("{{date when \"%Y-%m-%d\"}}").unwrap(), "2023-11-14");
+    assert_eq!(render("{{date since \"%H:%M\"}}").unwrap(), "22:13");
+    assert!(matches!(render("{{date name}}"), Err(Error::TemplateRender { field: "title", .. })));
+    assert!(render("{{date when \"%Q\"}}").is_err());
+
+    assert_eq!(render("{{truncate name 5}}").unwrap(), "Lorem…");
+    assert_eq!(render("{{truncate name 5 suffix=\"...\"}}").unwrap(), "Lorem...");
+    assert_eq!(render("{{truncate name 50}}").unwrap(), "Lorem ipsum dolor");
+    assert_eq!(render("{{join (truncate devices 2)}}").unwrap(), "laptop, phone");
+
+    assert_eq!(render("{{pluralize 1 \"device\"}}").unwrap(), "1 device");
+    assert_eq!(render("{{pluralize 2 \"device\"}}").unwrap(), "2 devices");
+    assert_eq!(render("{{pluralize 0 \"person\" \"people\"}}").unwrap(), "0 people");
+    assert_eq!(render("{{pluralize devices \"device\"}}, {{pluralize one \"device\"}}").unwrap(), "4 devices, 1 device");
+
+    assert_eq!(render("{{join devices \" / \"}}").unwrap(), "laptop / phone / tablet / watch");
+    assert_eq!(render("{{upper name}}").unwrap(), "LOREM IPSUM DOLOR");
+    assert_eq!(render("{{upper (join one)}}").unwrap(), "LAPTOP");
+
+    assert_eq!(render("{{default missing \"none\"}}").unwrap(), "none");
+    assert_eq!(render("{{default empty \"none\"}}").unwrap(), "none");
+    assert_eq!(render("{{default name \"none\"}}").unwrap(), "Lorem ipsum dolor");
+
+    // Output is still escaped, and helpers are available to every notification.
+    assert_eq!(render("{{default missing \"<none>\"}}").unwrap(), "&lt;none&gt;");
+    let mut notification = Notification::init("example", "Devices", "Devices connected.");
+    notification.add_serde_json_value("devices".to_string(), json!(["laptop", "phone", "tablet"]));
+    notification.set_title_template("{{upper app}}: {{pluralize devices \"device\"}}".to_string());
+    assert_eq!(notification.render().unwrap().title, "EXAMPLE: 3 devices");
+}
+
+#[test]
+fn test_custom_helpers() {
+    let engine = TemplateEngine::default();
+    engine.register_helper("format_mac", |params, hash| {
+        let address = params.first().and_then(Value::as_str).ok_or("expected a MAC address")?;
+        let separator = hash.get("sep").and_then(Value::as_str).unwrap_or(":");
+        if address.len() != 12 {
+            return Err(format!("invalid MAC address {}", address).into());
+        }
+        let octets: Vec<&str> = (0..12).step_by(2).map(|i| &address[i..i + 2]).collect();
+        Ok(json!(octets.join(separator).to_uppercase()))
+    });
+    engine.register_partial("dashboard", "<a href=\"https://dashboard.example.com/{{app}}\">{{app}}</a>").unwrap();
+    let values = json!({"app": "example", "mac": "a1b2c3d4e5f6", "short": "a1b2"});
+
+    assert_eq!(engine.render_inline("title", "{{format_mac mac}}", &values).unwrap(), "A1:B2:C3:D4:E5:F6");
+    assert_eq!(engine.render_inline("title", "{{format_mac mac sep=\"-\"}}", &values).unwrap(), "A1-B2-C3-D4-E5-F6");
+    assert_eq!(engine.render_inline("title", "{{truncate (format_mac mac) 5}}", &values).unwrap(), "A1:B2…");
+    match engine.render_inline("title", "{{format_mac short}}", &values) {
+        Err(Error::TemplateRender { field: "title", source }) => assert!(source.to_string().contains("invalid MAC address a1b2")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        engine.render_inline("long_html", "See {{> dashboard}}", &values).unwrap(),
+        "See <a href=\"https://dashboard.example.com/example\">example</a>"
+    );
+    assert!(matches!(engine.register_partial("broken", "{{#if}}"), Err(Error::TemplateCompile { field: "partial", .. })));
+
+    // Built-in helpers can be replaced.
+    engine.register_helper("upper", |params, _| Ok(json!(format!("{}!", params[0].as_str().unwrap_or_default().to_uppercase()))));
+    assert_eq!(engine.render_inline("title", "{{upper app}}", &values).unwrap(), "EXAMPLE!");
+
+    // Helpers and partials registered on the global engine are used by every notification.
+    TemplateEngine::global().register_helper("test_custom_helpers_reverse", |params, _| {
+        Ok(json!(params.first().and_then(Value::as_str).unwrap_or_default().chars().rev().collect::<String>()))
+    });
+    TemplateEngine::global().register_partial("test_custom_helpers_signature", "-- the {{app}} team").unwrap();
+    let mut notification = Notification::init("example", "An example", "Hello.");
+    notification.set_title_template("{{test_custom_helpers_reverse app}}".to_string());
+    notification.set_short_text_template("{{notification}}\n{{> test_custom_helpers_signature}}".to_string());
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.title, "elpmaxe");
+    assert_eq!(outbound.short_text, "Hello.\n-- the example team");
+}
+
+#[test]
+fn test_localized_templates() {
+    assert_eq!(engine::lang_chain("pt-BR"), vec!["pt-BR", "pt"]);
+    assert_eq!(engine::lang_chain("zh_Hant_TW"), vec!["zh_Hant_TW", "zh_Hant", "zh"]);
+    assert_eq!(engine::lang_chain("en-US"), vec!["en-US", "en"]);
+    assert!(engine::lang_chain("").is_empty());
+
+    // The default templates are localized, falling back to English.
+    let mut notification = Notification::init("example", "Ein Beispiel", "Hallo.");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.lang, "en");
+    assert!(outbound.short_text.ends_with("Email generated by example"));
+    assert!(outbound.long_html.contains("<HTML lang=\"en\">"));
+    notification.set_lang("de");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.title, "[example] Ein Beispiel");
+    assert_eq!(outbound.short_text, "Hallo.\n\n--\nE-Mail erstellt von example");
+    assert!(outbound.long_html.contains("<HTML lang=\"de\">"));
+    assert!(outbound.long_html.contains("E-Mail erstellt von example."));
+    notification.set_lang("pt-BR");
+    assert!(notification.render().unwrap().short_text.ends_with("E-mail gerado por example"));
+    notification.set_lang("ja");
+    assert!(notification.render().unwrap().short_text.ends_with("Email generated by example"));
+
+    // Languages can be added to the default templates.
+    TemplateEngine::global().register_template("rqpush.eo.text", "{{notification}}\n\n--\nRetpoŝto de {{tagline}}").unwrap();
+    notification.set_lang("eo");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.short_text, "Hallo.\n\n--\nRetpoŝto de example");
+    assert!(outbound.short_html.contains("Email generated by example."));
+
+    // Template sets fall back through the chain, then to their unlocalized
+    // templates, then to English, then to the localized defaults.
+    let engine = TemplateEngine::global();
+    engine.register_template("test-localized.pt.title", "Bem-vindo ao {{app}}").unwrap();
+    engine.register_template("test-localized.title", "Welcome to {{app}}").unwrap();
+    engine.register_template("test-localized.en.title", "Hello from {{app}}").unwrap();
+    engine.register_template("test-localized-only.fr.text", "{{notification}} -- {{app}}").unwrap();
+    engine.register_template("test-localized-only.en.title", "{{app}}: {{notification}}").unwrap();
+    let mut notification = Notification::init("example", "Welcome", "Hello.");
+    notification.set_template("test-localized");
+    notification.set_lang("pt-BR");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.title, "Bem-vindo ao example");
+    assert!(outbound.short_text.ends_with("E-mail gerado por example"));
+    notification.set_lang("fr");
+    assert_eq!(notification.render().unwrap().title, "Welcome to example");
+    notification.set_lang("en-GB");
+    assert_eq!(notification.render().unwrap().title, "Hello from example");
+    notification.set_lang("fr");
+    notification.set_template("test-localized-only");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.title, "example: Welcome");
+    assert_eq!(outbound.short_text, "Hello. -- example");
+    assert!(outbound.short_html.contains("E-mail généré par example."));
+    notification.set_lang("en");
+    assert!(notification.render().unwrap().short_text.ends_with("Email generated by example"));
+}
+
+#[test]
+fn test_template_dir() {
+    let dir = std::env::temp_dir().join(format!("rqpush-test-templates-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dir-welcome.title.hbs"), "Welcome to {{app}}: {{notification}}").unwrap();
+    std::fs::write(dir.join("dir-welcome.text.hbs"), "{{notification}}\n-- {{app}} team").unwrap();
+    std::fs::write(dir.join("dir-alert.html.hbs"), "<b>{{notification}}</b>").unwrap();
+    std::fs::write(dir.join("README.md"), "Not a template").unwrap();
+    assert_eq!(TemplateEngine::global().load_dir(&dir).unwrap(), 3);
+
+    // Notifications render with the templates named by set_template.
+    let mut notification = Notification::init("example", "An example", "This is an example.");
+    notification.set_template("dir-welcome");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.title, "Welcome to example: An example");
+    assert_eq!(outbound.short_text, "This is an example.\n-- example team");
+    assert_eq!(outbound.long_text, "This is an example.\n-- example team");
+    // Templates the set leaves out use the defaults.
+    assert!(outbound.short_html.contains("<BODY>"));
+
+    // Templates set on the notification take precedence.
+    notification.set_title_template("{{notification}}!".to_string());
+    assert_eq!(notification.render().unwrap().title, "An example!");
+    notification.set_template("dir-alert");
+    let outbound = notification.render().unwrap();
+    assert_eq!(outbound.short_html, "<b>This is an example.</b>");
+    assert!(outbound.short_text.contains("Email generated by example"));
+
+    // Unknown template sets and unreadable directories are reported.
+    notification.set_template("dir-missing");
+    assert!(matches!(notification.render(), Err(Error::InvalidInput { field: "template", .. })));
+    assert!(matches!(TemplateEngine::global().load_dir(dir.join("missing")), Err(Error::Io { .. })));
+    std::fs::write(dir.join("dir-broken.title.hbs"), "{{#if}}").unwrap();
+    assert!(matches!(TemplateEngine::new(1).load_dir(&dir), Err(Error::TemplateCompile { .. })));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "hot-reload")]
+#[test]
+fn test_template_watch() {
+    let dir = std::env::temp_dir().join(format!("rqpush-test-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("watched.title.hbs"), "v1 {{notification}}").unwrap();
+    let engine = TemplateEngine::default();
+    let watcher = engine.watch_dir(&dir).unwrap();
+    let values = json!({"notification": "An example"});
+    assert_eq!(engine.render("watched.title", &values).unwrap(), "v1 An example");
+    assert!(engine.has_set("watched"));
+
+    // Changed and removed files are picked up without reloading.
+    let wait_for = |expected: Option<&str>| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while engine.render("watched.title", &values).ok().as_deref() != expected {
+            assert!(Instant::now() < deadline, "template not reloaded");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+    std::fs::write(dir.join("watched.title.hbs"), "v2 {{notification}}").unwrap();
+    wait_for(Some("v2 An example"));
+    std::fs::write(dir.join("watched.title.hbs"), "{{#if}}").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(engine.render("watched.title", &values).unwrap(), "v2 An example");
+    std::fs::remove_file(dir.join("watched.title.hbs")).unwrap();
+    wait_for(None);
+    assert!(!engine.has_set("watched"));
+    drop(watcher);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, trace};
+use lru::LruCache;
+use subtle::ConstantTimeEq;
+
+use crate::spool::unix_now;
+use crate::{generate_sha256, verify_message_hmac, Message, OutboundNotification, SecretKeyring, SignatureVersion};
+
+/// Default clock skew tolerated by ReplayVerifier.
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(300);
+/// Default number of nonces remembered by LruNonceStore.
+pub const DEFAULT_NONCE_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why a received message failed verification.
+pub enum VerifyError {
+    /// The message has no sha256.
+    MissingHash,
+    /// The sha256 doesn't match the contents, or was salted with another secret.
+    HashMismatch,
+    /// The message has no signature.
+    MissingSignature,
+    /// The message is signed with a scheme the verifier doesn't accept.
+    UnsupportedSignature(Option<SignatureVersion>),
+    /// The signature doesn't match the message.
+    SignatureMismatch,
+    /// The message has no timestamp or nonce, so it can't be checked for replay.
+    MissingReplayProtection,
+    /// The message timestamp is too far from the current time.
+    ClockSkew {
+        /// The message timestamp, in seconds since the Unix epoch.
+        timestamp: u64,
+        /// The verifier's current time, in seconds since the Unix epoch.
+        now: u64,
+    },
+    /// The message nonce has been seen before.
+    Replayed(String),
+    /// The message has no key id, so the key to verify it with is unknown.
+    MissingKeyId,
+    /// The message key id isn't in the keyring.
+    UnknownKey(String),
+    /// The message contents aren't a valid OutboundNotification.
+    MalformedContents(String),
+    /// The message contents were expected to be encrypted, but aren't.
+    NotEncrypted,
+    /// The message contents are encrypted, and must be opened with ContentKey::open.
+    Encrypted,
+    /// The message contents couldn't be decrypted, because the key is wrong or
+    /// they were tampered with.
+    DecryptionFailed,
+    /// The message was signed with a key belonging to a different app.
+    AppMismatch {
+        /// The key id the message was signed with.
+        key_id: String,
+        /// The app the key belongs to.
+        expected: String,
+        /// The app named by the notification.
+        actual: String,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::MissingHash => write!(f, "message has no sha256"),
+            VerifyError::HashMismatch => write!(f, "sha256 does not match message"),
+            VerifyError::MissingSignature => write!(f, "message is not signed"),
+            VerifyError::UnsupportedSignature(version) => write!(f, "unsupported signature version {:?}", version),
+            VerifyError::SignatureMismatch => write!(f, "signature does not match message"),
+            VerifyError::MissingReplayProtection => write!(f, "message has no timestamp or nonce"),
+            VerifyError::ClockSkew { timestamp, now } => {
+                write!(f, "message timestamp {} is too far from current time {}", timestamp, now)
+            }
+            VerifyError::Replayed(nonce) => write!(f, "message nonce {} has already been seen", nonce),
+            VerifyError::MissingKeyId => write!(f, "message has no key id"),
+            VerifyError::UnknownKey(key_id) => write!(f, "unknown key id {}", key_id),
+            VerifyError::MalformedContents(reason) => write!(f, "malformed message contents: {}", reason),
+            VerifyError::NotEncrypted => write!(f, "message contents are not encrypted"),
+            VerifyError::Encrypted => write!(f, "message contents are encrypted"),
+            VerifyError::DecryptionFailed => write!(f, "failed to decrypt message contents"),
+            VerifyError::AppMismatch { key_id, expected, actual } => {
+                write!(f, "key {} signs for app {}, not {}", key_id, expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl Message {
+    /// Verifies the message was sent with `shared_secret`. Legacy messages are
+    /// checked against their salted sha256, HMAC signed messages against their
+    /// unsalted sha256 and signature. Comparisons are constant time. Replays
+    /// aren't detected, see ReplayVerifier.
+    pub fn verify(&self, shared_secret: Option<&str>) -> Result<(), VerifyError> {
+        let sha256 = self.sha256.as_deref().ok_or(VerifyError::MissingHash)?;
+        let signed = self.signature_version.is_some() || self.signature.is_some();
+        let salt = if signed { None } else { shared_secret };
+        let expected = generate_sha256(&self.contents, salt);
+        if !bool::from(expected.as_bytes().ct_eq(sha256.to_ascii_lowercase().as_bytes())) {
+            return Err(VerifyError::HashMismatch);
+        }
+        match (signed, shared_secret) {
+            (false, _) => Ok(()),
+            (true, Some(shared_secret)) => verify_message_hmac(self, shared_secret),
+            (true, None) => Err(VerifyError::UnsupportedSignature(self.signature_version)),
+        }
+    }
+
+    /// Verifies the message as `verify` does, then parses its contents. Encrypted
+    /// messages should instead be verified, then opened with ContentKey::open.
+    pub fn verify_and_open(&self, shared_secret: Option<&str>) -> Result<OutboundNotification, VerifyError> {
+        trace!("rqpush verify_and_open: message({:?})", &self);
+        self.verify(shared_secret)?;
+        if self.encryption.is_some() {
+            return Err(VerifyError::Encrypted);
+        }
+        serde_json::from_str(&self.contents).map_err(|e| VerifyError::MalformedContents(e.to_string()))
+    }
+}
+
+/// Remembers nonces of verified messages, so replays can be detected.
+pub trait NonceStore: Send + Sync {
+    /// Records `nonce`, returning false if it had already been recorded.
+    fn insert(&self, nonce: &str, timestamp: u64) -> bool;
+}
+
+#[derive(Debug)]
+/// An in-memory NonceStore remembering the most recently seen nonces. Its
+/// capacity should comfortably exceed the number of messages received within
+/// the verifier's clock skew window, as older nonces are forgotten.
+pub struct LruNonceStore {
+    nonces: Mutex<LruCache<String, u64>>,
+}
+
+impl LruNonceStore {
+    /// Creates a store remembering up to `capacity` nonces.
+    pub fn new(capacity: usize) -> LruNonceStore {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        LruNonceStore {
+            nonces: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl Default for LruNonceStore {
+    fn default() -> LruNonceStore {
+        LruNonceStore::new(DEFAULT_NONCE_CAPACITY)
+    }
+}
+
+impl NonceStore for LruNonceStore {
+    fn insert(&self, nonce: &str, timestamp: u64) -> bool {
+        let mut nonces = self.nonces.lock().expect("nonce store poisoned");
+        if nonces.contains(nonce) {
+            return false;
+        }
+        nonces.put(nonce.to_string(), timestamp);
+        true
+    }
+}
+
+/// Verifies messages signed by Signer::HmacSha256 or Signer::Keyring, rejecting
+/// any whose timestamp is outside the allowed clock skew or whose nonce was
+/// already seen.
+pub struct ReplayVerifier<S: NonceStore = LruNonceStore> {
+    keyring: SecretKeyring,
+    max_skew: Duration,
+    store: S,
+}
+
+impl ReplayVerifier<LruNonceStore> {
+    /// Creates a verifier remembering nonces in a default LruNonceStore. Takes
+    /// either a single shared secret or a SecretKeyring.
+    pub fn new<K: Into<SecretKeyring>>(keyring: K) -> ReplayVerifier<LruNonceStore> {
+        ReplayVerifier::with_store(keyring, LruNonceStore::default())
+    }
+}
+
+impl<S: NonceStore> ReplayVerifier<S> {
+    /// Creates a verifier remembering nonces in `store`.
+    pub fn with_store<K: Into<SecretKeyring>>(keyring: K, store: S) -> ReplayVerifier<S> {
+        ReplayVerifier {
+            keyring: keyring.into(),
+            max_skew: DEFAULT_MAX_SKEW,
+            store,
+        }
+    }
+
+    /// How far a message timestamp may be from the current time (otherwise will
+    /// default to DEFAULT_MAX_SKEW).
+    pub fn max_skew(mut self, max_skew: Duration) -> ReplayVerifier<S> {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Verifies the message against the current time.
+    pub fn verify(&self, message: &Message) -> Result<(), VerifyError> {
+        self.verify_at(message, unix_now())
+    }
+
+    /// Verifies the message as if the current time were `now`, in seconds since
+    /// the Unix epoch. The signature is checked before the nonce is recorded, so
+    /// forged messages can't fill the nonce store.
+    pub fn verify_at(&self, message: &Message, now: u64) -> Result<(), VerifyError> {
+        trace!("rqpush verify_at: now({}) message({:?})", now, &message);
+        if message.signature_version != Some(SignatureVersion::HmacSha256V2) {
+            return Err(match message.signature {
+                Some(_) => VerifyError::UnsupportedSignature(message.signature_version),
+                None => VerifyError::MissingSignature,
+            });
+        }
+        self.keyring.verify(message)?;
+        let (timestamp, nonce) = match (message.timestamp, &message.nonce) {
+            (Some(timestamp), Some(nonce)) => (timestamp, nonce),
+            _ => return Err(VerifyError::MissingReplayProtection),
+        };
+        let skew = timestamp.abs_diff(now);
+        if skew > self.max_skew.as_secs() {
+            debug!("rqpush rejecting message with timestamp {} at {}", timestamp, now);
+            return Err(VerifyError::ClockSkew { timestamp, now });
+        }
+        if !self.store.insert(nonce, timestamp) {
+            debug!("rqpush rejecting replayed nonce {}", nonce);
+            return Err(VerifyError::Replayed(nonce.clone()));
+        }
+        Ok(())
+    }
+}